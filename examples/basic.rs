@@ -1,8 +1,9 @@
-use dtm::DTM;
+use dtm::{SampleFormat, DTM};
 
 fn main() {
     let descriptor1 = DTM {
         pixel_size: 2,
+        sample_format: SampleFormat::Unsigned,
         channel_count: 1,
         width: 16,
         height: 16,
@@ -1,4 +1,4 @@
-use dtm::DTM;
+use dtm::{SampleFormat, DTM};
 use std::{fs, time::Instant};
 
 fn main() {
@@ -23,6 +23,7 @@ fn main() {
         let start = Instant::now();
         DTM {
             pixel_size: 2,
+            sample_format: SampleFormat::Unsigned,
             channel_count: 1,
             width: source.width(),
             height: source.height(),
@@ -1,10 +1,11 @@
-use dtm::DTM;
+use dtm::{SampleFormat, DTM};
 
 fn main() {
     let source = image::open("images/input/minmax.png").unwrap();
 
     let descriptor1 = DTM {
         pixel_size: 2,
+        sample_format: SampleFormat::Unsigned,
         channel_count: 2,
         width: source.width(),
         height: source.height(),
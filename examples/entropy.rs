@@ -0,0 +1,129 @@
+//! Compares the size and the decoding speed of the opcodes and the Rice coded residuals
+//! of `Encoder::entropy_coding` on synthetic two channel terrain.
+//!
+//! Run with `cargo run --release --example entropy`.
+
+use dtm::{encode::Encoder, SampleFormat, DTM};
+use std::time::Instant;
+
+const SIZE: usize = 1024;
+
+fn main() {
+    let heights = terrain(SIZE);
+
+    for (name, sample_format, pixel_size) in [
+        ("u16", SampleFormat::Unsigned, 2),
+        ("f32", SampleFormat::Float, 4),
+    ] {
+        // Heights quantised to metres contain many runs, which the opcodes encode quickly,
+        // while the noise mimics the measurement noise of elevation models in decimetres.
+        for (resolution, noise) in [(1.0, 0.0), (10.0, 0.0), (10.0, 8.0)] {
+            let descriptor = DTM {
+                pixel_size,
+                sample_format,
+                channel_count: 2,
+                width: SIZE as u32,
+                height: SIZE as u32,
+            };
+
+            let data = pixels(&heights, resolution, noise, sample_format);
+
+            let (opcodes, opcodes_decode) = measure(Encoder::new(descriptor), &data);
+            let (entropy, entropy_decode) =
+                measure(Encoder::new(descriptor).entropy_coding(true), &data);
+
+            println!(
+                "{name} resolution {resolution:>4} noise {noise:>3}: opcodes {:>8} bytes {:>7.2} ms | entropy {:>8} bytes {:>7.2} ms | size {:.2}x, decoding {:.1}x slower",
+                opcodes,
+                opcodes_decode,
+                entropy,
+                entropy_decode,
+                entropy as f32 / opcodes as f32,
+                entropy_decode / opcodes_decode,
+            );
+        }
+    }
+}
+
+/// Encodes the pixels and returns the encoded size and the fastest of several decoding times.
+fn measure(encoder: Encoder, data: &[u8]) -> (usize, f32) {
+    let encoded = encoder.encode_alloc(data).unwrap();
+    let mut fastest = f32::MAX;
+
+    for _ in 0..5 {
+        let start = Instant::now();
+        let (_, decoded) = DTM::decode_alloc(&encoded).unwrap();
+        fastest = fastest.min(start.elapsed().as_secs_f32() * 1000.0);
+
+        assert_eq!(decoded, data);
+    }
+
+    (encoded.len(), fastest)
+}
+
+/// Returns the interleaved minimum and maximum heights in multiples of `1 / resolution` metres,
+/// which are offset by noise.
+fn pixels(heights: &[f64], resolution: f64, noise: f64, sample_format: SampleFormat) -> Vec<u8> {
+    let mut random = Random(7);
+    let mut data = Vec::new();
+
+    for &height in heights {
+        let min = (height * resolution + random.next() * noise).floor();
+        let max = (min + 5.0 + random.next() * noise).floor();
+
+        for value in [min, max] {
+            match sample_format {
+                SampleFormat::Float => {
+                    data.extend_from_slice(&((value / resolution) as f32).to_le_bytes())
+                }
+                _ => data.extend_from_slice(&(value as u16).to_le_bytes()),
+            }
+        }
+    }
+
+    data
+}
+
+/// Returns the heights of fractal terrain, which sums octaves of bilinearly interpolated value noise.
+fn terrain(size: usize) -> Vec<f64> {
+    let mut random = Random(3);
+    let mut heights = vec![0.0; size * size];
+
+    for octave in 0..8 {
+        let cell = 512 >> octave;
+        let amplitude = 3000.0 / (1 << octave) as f64;
+        let grid_size = size / cell + 2;
+        let grid: Vec<f64> = (0..grid_size * grid_size).map(|_| random.next()).collect();
+
+        for (index, height) in heights.iter_mut().enumerate() {
+            let (x, y) = ((index % size) as f64, (index / size) as f64);
+            let (x, y) = (x / cell as f64, y / cell as f64);
+            let (column, row) = (x as usize, y as usize);
+            let (x, y) = (x - column as f64, y - row as f64);
+            let corner = |dx: usize, dy: usize| grid[(row + dy) * grid_size + column + dx];
+
+            *height += amplitude
+                * (corner(0, 0) * (1.0 - x) * (1.0 - y)
+                    + corner(1, 0) * x * (1.0 - y)
+                    + corner(0, 1) * (1.0 - x) * y
+                    + corner(1, 1) * x * y);
+        }
+    }
+
+    heights
+}
+
+/// A linear congruential generator, so that the terrain is the same on every run.
+struct Random(u64);
+
+impl Random {
+    /// Returns a number between zero and one.
+    fn next(&mut self) -> f64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,9 @@
+#![no_main]
+
+use dtm::DTM;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|encoded: &[u8]| {
+    let mut decoded = vec![0; 1 << 20];
+    let _ = DTM::decode(encoded, &mut decoded);
+});
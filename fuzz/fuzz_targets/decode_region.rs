@@ -0,0 +1,25 @@
+#![no_main]
+
+use dtm::{info::DtmInfo, DTM};
+use libfuzzer_sys::fuzz_target;
+
+// The first four bytes select the region, which is small enough to allocate it.
+fuzz_target!(|data: &[u8]| {
+    let Some((region, encoded)) = data.split_first_chunk::<4>() else {
+        return;
+    };
+
+    let Ok(info) = DtmInfo::from_slice(encoded) else {
+        return;
+    };
+
+    let descriptor = info.descriptor;
+    let x = (region[0] as u32).min(descriptor.width);
+    let y = (region[1] as u32).min(descriptor.height);
+    let width = (region[2] as u32).min(descriptor.width - x);
+    let height = (region[3] as u32).min(descriptor.height - y);
+
+    let size = (width * height * descriptor.pixel_size * descriptor.channel_count) as usize;
+    let mut decoded = vec![0; size];
+    let _ = DTM::decode_region(encoded, x, y, width, height, &mut decoded);
+});
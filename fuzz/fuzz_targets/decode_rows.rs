@@ -0,0 +1,22 @@
+#![no_main]
+
+use dtm::{info::DtmInfo, DTM};
+use libfuzzer_sys::fuzz_target;
+
+// The first two bytes select the rows, which are decoded into a buffer of at most 1 MiB.
+fuzz_target!(|data: &[u8]| {
+    let Some((rows, encoded)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+
+    let Ok(info) = DtmInfo::from_slice(encoded) else {
+        return;
+    };
+
+    let start = (rows[0] as u32).min(info.descriptor.height);
+    let end = (start + rows[1] as u32).min(info.descriptor.height);
+
+    let size = info.descriptor.row_size() * (end - start) as usize;
+    let mut decoded = vec![0; size.min(1 << 20)];
+    let _ = DTM::decode_rows(encoded, start..end, &mut decoded);
+});
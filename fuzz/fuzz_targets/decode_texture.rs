@@ -0,0 +1,32 @@
+#![no_main]
+
+use dtm::{decode::TexelLayout, DTM};
+use libfuzzer_sys::fuzz_target;
+
+// The first byte selects the texel layout and the padding of the rows.
+fuzz_target!(|data: &[u8]| {
+    let Some((&selector, encoded)) = data.split_first() else {
+        return;
+    };
+
+    let layout = match selector % 3 {
+        0 => TexelLayout::Channels,
+        1 => TexelLayout::Rg,
+        _ => TexelLayout::Rgba,
+    };
+
+    let Ok((descriptor, _)) = DTM::decode_metadata(encoded) else {
+        return;
+    };
+
+    let texel_size =
+        (layout.channel_count(descriptor.channel_count) * descriptor.pixel_size) as usize;
+    let row_pitch = texel_size * descriptor.width as usize + (selector / 3) as usize;
+    let mut decoded = vec![
+        0;
+        row_pitch
+            .saturating_mul(descriptor.height as usize)
+            .min(1 << 20)
+    ];
+    let _ = DTM::decode_texture(encoded, &mut decoded, row_pitch, layout);
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use dtm::{reader::DtmReader, DTM};
+use dtm_fuzz::is_large;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|encoded: &[u8]| {
+    // The reader allocates its row buffers up front, so only the header of large images is parsed.
+    if is_large(encoded) {
+        let _ = DTM::decode_metadata(encoded);
+    } else if let Ok(mut reader) = DtmReader::new(encoded) {
+        let _ = reader.read_rows(|_| {});
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use dtm::DTM;
+use dtm_fuzz::is_large;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|encoded: &[u8]| {
+    if is_large(encoded) {
+        let _ = DTM::decode_metadata(encoded);
+    } else {
+        let _ = DTM::verify(encoded);
+    }
+});
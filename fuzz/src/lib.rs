@@ -0,0 +1,20 @@
+//! Helpers shared by the fuzz targets.
+
+use dtm::info::DtmInfo;
+
+/// The largest decoded image in bytes, which the targets decode in full.
+const MAX_IMAGE_SIZE: usize = 1 << 24;
+
+/// The largest number of rows, which the targets decode in full.
+const MAX_HEIGHT: u32 = 1 << 16;
+
+/// Returns whether the image is too large to decode it quickly, though it may be valid.
+///
+/// Masked pixels are not encoded, but decoded in full, so a few bytes may declare a huge image.
+/// The targets still parse the header of such images, but do not decode their pixels.
+pub fn is_large(encoded: &[u8]) -> bool {
+    DtmInfo::from_slice(encoded).is_ok_and(|info| {
+        let descriptor = info.descriptor;
+        descriptor.image_size() > MAX_IMAGE_SIZE || descriptor.height > MAX_HEIGHT
+    })
+}
@@ -0,0 +1,221 @@
+//! Metadata chunks, which are stored in the chunk section after the header.
+//!
+//! Every chunk consists of a four byte id, the length of its data (u32) and the data itself.
+//! Like in PNG, the case of the first letter of the id marks whether a chunk is critical.
+//! Decoders skip unknown ancillary chunks, but reject images containing unknown critical chunks,
+//! as those change how the pixels have to be interpreted.
+
+use crate::decode::DecodeError;
+use std::fmt::{self, Debug, Display};
+
+/// The id of a chunk, which consists of four ASCII letters.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkId(pub [u8; 4]);
+
+impl ChunkId {
+    /// A key/value pair of UTF-8 text, separated by a zero byte.
+    pub const TEXT: ChunkId = ChunkId(*b"tEXt");
+
+    /// Returns whether the chunk has to be understood by the decoder.
+    ///
+    /// This is the case, if bit 5 of the first byte is clear (i.e. the first letter is uppercase).
+    #[inline]
+    pub fn is_critical(self) -> bool {
+        self.0[0] & 0x20 == 0
+    }
+}
+
+impl Display for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+impl Debug for ChunkId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ChunkId({self})")
+    }
+}
+
+/// A chunk of metadata.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Chunk {
+    pub id: ChunkId,
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Creates a chunk with the given id and data.
+    #[inline]
+    pub fn new(id: ChunkId, data: Vec<u8>) -> Self {
+        Self { id, data }
+    }
+
+    /// Creates a text chunk holding the key/value pair.
+    ///
+    /// The key must not contain a zero byte.
+    pub fn text(key: &str, value: &str) -> Self {
+        let mut data = Vec::with_capacity(key.len() + 1 + value.len());
+        data.extend_from_slice(key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value.as_bytes());
+
+        Self::new(ChunkId::TEXT, data)
+    }
+
+    /// Returns the key/value pair of a text chunk.
+    ///
+    /// Returns `None` if the chunk is no text chunk or its data is malformed.
+    pub fn as_text(&self) -> Option<(&str, &str)> {
+        if self.id != ChunkId::TEXT {
+            return None;
+        }
+
+        let separator = self.data.iter().position(|&byte| byte == 0)?;
+        let key = std::str::from_utf8(&self.data[..separator]).ok()?;
+        let value = std::str::from_utf8(&self.data[separator + 1..]).ok()?;
+
+        Some((key, value))
+    }
+}
+
+/// The critical chunks understood by the decoder.
+const SUPPORTED_CRITICAL_CHUNKS: &[ChunkId] = &[ChunkId::MASK];
+
+/// Appends the chunks to the chunk section.
+pub(crate) fn encode_chunks(chunks: &[Chunk], section: &mut Vec<u8>) {
+    for chunk in chunks {
+        section.extend_from_slice(&chunk.id.0);
+        section.extend_from_slice(&(chunk.data.len() as u32).to_be_bytes());
+        section.extend_from_slice(&chunk.data);
+    }
+}
+
+/// An iterator over the chunks of a chunk section, which borrows their data.
+pub(crate) struct Chunks<'a> {
+    section: &'a [u8],
+}
+
+impl<'a> Chunks<'a> {
+    #[inline]
+    pub(crate) fn new(section: &'a [u8]) -> Self {
+        Self { section }
+    }
+
+    /// Copies the chunks out of the chunk section.
+    pub(crate) fn into_vec(self) -> Result<Vec<Chunk>, DecodeError> {
+        self.map(|chunk| chunk.map(|(id, data)| Chunk::new(id, data.to_vec())))
+            .collect()
+    }
+
+    /// Ensures that the chunk section is well-formed and contains no unknown critical chunks.
+    pub(crate) fn validate(self) -> Result<(), DecodeError> {
+        for chunk in self {
+            let (id, _) = chunk?;
+
+            if id.is_critical() && !SUPPORTED_CRITICAL_CHUNKS.contains(&id) {
+                return Err(DecodeError::UnsupportedChunk(id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = Result<(ChunkId, &'a [u8]), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.section.is_empty() {
+            return None;
+        }
+
+        let header = match self.section.get(..8) {
+            Some(header) => header,
+            None => {
+                self.section = &[];
+                return Some(Err(DecodeError::InvalidChunkSection));
+            }
+        };
+
+        let id = ChunkId(header[0..4].try_into().unwrap());
+        let length = u32::from_be_bytes(header[4..8].try_into().unwrap()) as usize;
+
+        let data = match self.section.get(8..8 + length) {
+            Some(data) => data,
+            None => {
+                self.section = &[];
+                return Some(Err(DecodeError::InvalidChunkSection));
+            }
+        };
+
+        self.section = &self.section[8 + length..];
+
+        Some(Ok((id, data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        testing::{descriptor, features, roundtrip, terrain},
+        SampleFormat, DTM, FEATURE_CHUNKS,
+    };
+
+    #[test]
+    fn roundtrips_chunks() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let chunks = vec![
+            Chunk::text("source", "survey 2024"),
+            Chunk::new(ChunkId(*b"xTRa"), vec![1, 2, 3]),
+            Chunk::new(ChunkId(*b"eMPt"), Vec::new()),
+        ];
+
+        let encoder = chunks
+            .iter()
+            .fold(Encoder::new(descriptor), |encoder, chunk| {
+                encoder.chunk(chunk.clone())
+            });
+        let encoded = roundtrip(&encoder, &terrain(&descriptor));
+
+        assert_eq!(features(&encoded), FEATURE_CHUNKS);
+        assert_eq!(DTM::read_chunks(&encoded).unwrap(), chunks);
+        assert_eq!(chunks[0].as_text(), Some(("source", "survey 2024")));
+    }
+
+    #[test]
+    fn rejects_unknown_critical_chunks() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let encoder = Encoder::new(descriptor).chunk(Chunk::new(ChunkId(*b"CRIt"), vec![0]));
+        let encoded = encoder.encode_alloc(&terrain(&descriptor)).unwrap();
+
+        assert!(matches!(
+            DTM::decode_alloc(&encoded),
+            Err(DecodeError::UnsupportedChunk(ChunkId(id))) if &id == b"CRIt"
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_chunk_sections() {
+        let mut section = Vec::new();
+        encode_chunks(&[Chunk::text("key", "value")], &mut section);
+
+        for size in [3, 8, section.len() - 1] {
+            assert!(matches!(
+                Chunks::new(&section[..size]).into_vec(),
+                Err(DecodeError::InvalidChunkSection)
+            ));
+        }
+    }
+
+    #[test]
+    fn decodes_metadata_of_regression_file() {
+        let encoded = include_bytes!("../tests/data/chunks.dtm");
+        let (_, metadata) = DTM::decode_metadata(encoded).unwrap();
+
+        assert_eq!(metadata.nodata[0], Some(500));
+        assert_eq!(metadata.scales[0].as_ref().unwrap().unit, "m");
+    }
+}
@@ -0,0 +1,135 @@
+/// The reversed CRC-32 (IEEE 802.3) polynomial, as used by zlib and PNG.
+const POLYNOMIAL: u32 = 0xEDB88320;
+
+/// The lookup tables of the slice-by-8 algorithm, which are computed at compile time.
+///
+/// `TABLES[k][b]` is the checksum update of the byte `b` followed by `k` zero bytes.
+static TABLES: [[u32; 256]; 8] = tables();
+
+const fn tables() -> [[u32; 256]; 8] {
+    let mut tables = [[0; 256]; 8];
+
+    let mut byte = 0;
+    while byte < 256 {
+        let mut crc = byte as u32;
+
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ POLYNOMIAL
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        tables[0][byte] = crc;
+        byte += 1;
+    }
+
+    let mut byte = 0;
+    while byte < 256 {
+        let mut k = 1;
+        while k < 8 {
+            let crc = tables[k - 1][byte];
+            tables[k][byte] = crc >> 8 ^ tables[0][(crc & 0xFF) as usize];
+            k += 1;
+        }
+
+        byte += 1;
+    }
+
+    tables
+}
+
+/// An incremental CRC-32 checksum.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32 {
+    state: u32,
+}
+
+impl Crc32 {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Adds the bytes to the checksum.
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        let mut crc = self.state;
+
+        let mut chunks = bytes.chunks_exact(8);
+
+        for chunk in &mut chunks {
+            let low = u32::from_le_bytes(chunk[0..4].try_into().unwrap()) ^ crc;
+            let high = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+
+            crc = TABLES[7][(low & 0xFF) as usize]
+                ^ TABLES[6][(low >> 8 & 0xFF) as usize]
+                ^ TABLES[5][(low >> 16 & 0xFF) as usize]
+                ^ TABLES[4][(low >> 24) as usize]
+                ^ TABLES[3][(high & 0xFF) as usize]
+                ^ TABLES[2][(high >> 8 & 0xFF) as usize]
+                ^ TABLES[1][(high >> 16 & 0xFF) as usize]
+                ^ TABLES[0][(high >> 24) as usize];
+        }
+
+        for &byte in chunks.remainder() {
+            crc = crc >> 8 ^ TABLES[0][((crc ^ byte as u32) & 0xFF) as usize];
+        }
+
+        self.state = crc;
+    }
+
+    /// Returns the checksum of all bytes added so far.
+    #[inline]
+    pub(crate) fn finish(self) -> u32 {
+        !self.state
+    }
+
+    /// Returns the checksum of the bytes.
+    #[inline]
+    pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(bytes);
+        crc.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode::DecodeError, DTM};
+
+    #[test]
+    fn matches_check_value() {
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF43926);
+        assert_eq!(Crc32::checksum(&[]), 0);
+    }
+
+    #[test]
+    fn updates_incrementally() {
+        let bytes: Vec<u8> = (0..1000u32).map(|i| (i * 7919 % 251) as u8).collect();
+
+        for split in [0, 1, 7, 8, 9, 500, 999] {
+            let mut crc = Crc32::new();
+            crc.update(&bytes[..split]);
+            crc.update(&bytes[split..]);
+
+            assert_eq!(crc.finish(), Crc32::checksum(&bytes));
+        }
+    }
+
+    #[test]
+    fn detects_corrupt_channels() {
+        let mut encoded = include_bytes!("../tests/data/checksums.dtm").to_vec();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+
+        assert!(matches!(
+            DTM::decode_alloc(&encoded),
+            Err(DecodeError::ChecksumMismatch { channel: 2 })
+        ));
+        assert!(DTM::verify(&encoded).is_err());
+    }
+}
@@ -1,172 +1,1951 @@
 use crate::{
-    CACHE, CACHE_END, DEFAULT, DOUBLE_DIFF, DOUBLE_DIFF_END, DOUBLE_DIFF_RANGE, DTM,
-    DTM_HEADER_SIZE, DTM_MAGIC, MASK_3BIT, MASK_6BIT, RUN_LENGTH, RUN_LENGTH_END, SINGLE_DIFF,
-    SINGLE_DIFF_END, SINGLE_DIFF_RANGE,
+    chunk::{Chunk, ChunkId, Chunks},
+    crc::Crc32,
+    mask::{decode_masks, ChannelMask, Masks},
+    metadata::{Metadata, Scale},
+    pixel::{with_pixel, Element, Pixel, Signed},
+    predictor::{self, Predictor},
+    reader::{DtmReader, RowRing},
+    rice::{self, ResidualDecoder},
+    tile::{Rect, Tiles, Tiling},
+    Coding, SampleFormat, CACHE, CACHE_END, DEFAULT, DOUBLE_DIFF, DOUBLE_DIFF_END,
+    DOUBLE_DIFF_RANGE, DTM, DTM_HEADER_SIZE, DTM_LEGACY_HEADER_SIZE, DTM_MAGIC, DTM_VERSION,
+    FEATURE_CHECKSUMS, FEATURE_CHUNKS, FEATURE_ENTROPY, FEATURE_INTER_CHANNEL, FEATURE_PREDICTORS,
+    FEATURE_TILES, MASK_3BIT, MASK_6BIT, RUN_LENGTH, RUN_LENGTH_END, SINGLE_DIFF, SINGLE_DIFF_END,
+    SINGLE_DIFF_RANGE, SUPPORTED_FEATURES, WIDE_DIFF,
 };
 use std::{
     error::Error,
     fmt::{self, Display},
-    fs,
+    fs, io,
+    ops::Range,
     path::Path,
 };
 
-struct Header {
-    descriptor: DTM,
-    channel_sizes: [usize; 4],
-    total_size: usize,
+#[cfg(feature = "parallel")]
+use std::{panic::resume_unwind, thread};
+
+/// The validity masks of the channels of an image, which are `None` for unmasked channels.
+pub type ValidityMasks = Vec<Option<Vec<bool>>>;
+
+/// The layout of the pixels written by [`DTM::decode_texture`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TexelLayout {
+    /// The channels of the image, e.g. R16 for an image of a single 16 bit channel.
+    #[default]
+    Channels,
+    /// Two channels per pixel, e.g. RG16.
+    Rg,
+    /// Four channels per pixel, e.g. RGBA16.
+    Rgba,
+}
+
+impl TexelLayout {
+    /// Returns the number of channels per pixel for an image with the given channel count.
+    #[inline]
+    pub fn channel_count(self, image_channel_count: u32) -> u32 {
+        match self {
+            TexelLayout::Channels => image_channel_count,
+            TexelLayout::Rg => 2,
+            TexelLayout::Rgba => 4,
+        }
+    }
+}
+
+pub(crate) struct Header {
+    pub(crate) descriptor: DTM,
+    pub(crate) channel_sizes: [usize; 4],
+    /// The checksums of the encoded channels, if the image stores them.
+    pub(crate) checksums: Option<[u32; 4]>,
+    /// The tiling of the channels and the offsets of the tiles, if the image is tiled.
+    pub(crate) tiles: Option<Tiles>,
+    /// The position of the chunks inside the header.
+    pub(crate) chunks: Range<usize>,
+    /// The coding of the compressed channels and tiles.
+    pub(crate) coding: Coding,
+    /// Whether every channel but the first stores the differences to the preceding channel.
+    pub(crate) inter_channel: bool,
+    /// The size of the header, after which the encoded channels start.
+    pub(crate) header_size: usize,
+    pub(crate) total_size: usize,
+}
+
+/// Errors that may occur during DTM image decoding.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The input buffer is shorter than the header.
+    InsufficientHeaderData,
+    /// The encoded header contains an invalid magic value.
+    ///
+    /// First four encoded must contain `b"dtm"`.
+    /// This usually indicates that the buffer does not contain a DTM image.
+    InvalidMagic,
+    /// The encoded header contains a version, which is not supported by the decoder.
+    ///
+    /// This usually indicates that the image was written by a newer encoder.
+    UnsupportedVersion(u8),
+    /// The encoded header enables features, which are not supported by the decoder.
+    ///
+    /// The value contains the unsupported feature flags.
+    UnsupportedFeatures(u32),
+    /// The encoded header contains an invalid pixel size value.
+    ///
+    /// DTM supports 8, 16 and 32 bit pixels.
+    InvalidPixelSize(u32),
+    /// The encoded header contains an unknown sample format
+    /// or one, which does not support the pixel size.
+    ///
+    /// Floating point pixels have to be 32 bit.
+    InvalidSampleFormat,
+    /// The encoded header contains dimensions, whose image size exceeds the addressable memory,
+    /// or whose pixels can not be stored in the sizes of the encoded channels.
+    InvalidDimensions { width: u32, height: u32 },
+    /// The size table contains an invalid channels value.
+    ///
+    /// DTM supports 1 to 4 channels, whose sizes are stored without gaps.
+    /// Any other value can not be produced by a valid encoder.
+    InvalidChannels,
+    /// The size table contains a channel size, which is larger than the uncompressed channel.
+    ///
+    /// Any such value can not be produced by a valid encoder.
+    InvalidChannelSize { channel: usize, size: usize },
+    /// The input buffer does not contain all of the encoded data of a channel.
+    ///
+    /// The `offset` is the number of bytes of the channel, which are available.
+    InsufficientChannelData { channel: usize, offset: usize },
+    /// The encoded data of a channel is malformed.
+    ///
+    /// The `offset` is the position of the offending byte relative to the start of the channel.
+    CorruptData { channel: usize, offset: usize },
+    /// The image does not contain the pixel type requested by a typed entry point.
+    ///
+    /// E.g. [`DTM::decode_i16`] requires a 16 bit image with signed samples.
+    SampleTypeMismatch,
+    /// The chunk section is malformed, i.e. a chunk extends past the end of the section.
+    InvalidChunkSection,
+    /// The chunk section contains a critical chunk, which is not supported by the decoder.
+    UnsupportedChunk(ChunkId),
+    /// The data of a known chunk is malformed.
+    InvalidChunk(ChunkId),
+    /// The encoded data of a channel does not match the checksum stored in the header.
+    ChecksumMismatch { channel: usize },
+    /// The tile size or the tile offset table in the header is invalid.
+    ///
+    /// Any such value can not be produced by a valid encoder.
+    InvalidTiling,
+    /// The requested region exceeds the bounds of the image.
+    InvalidRegion,
+    /// The output buffer is too small to fit the decoded image.
+    InsufficientOutputBuffer,
+    /// The row stride is smaller than a row of the decoded image.
+    InvalidStride,
+    /// The texel layout has fewer channels than the image.
+    InvalidTexelLayout,
+    /// The memory for the decoded image could not be allocated.
+    OutOfMemory,
+    /// An IO error occurred while loading the image.
+    IoError(io::Error),
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::IoError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InsufficientHeaderData => {
+                f.write_str("The input buffer is shorter than the header.")
+            }
+            DecodeError::InvalidMagic => {
+                f.write_str("The encoded header contains an invalid magic value.")
+            }
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "The encoded header contains the unsupported version {version}. The decoder supports up to version {DTM_VERSION}")
+            }
+            DecodeError::UnsupportedFeatures(features) => {
+                write!(f, "The encoded header enables the unsupported features {features:#010x}.")
+            }
+            DecodeError::InvalidPixelSize(pixel_size) => {
+                write!(f, "The encoded header contains an invalid pixel size value of {pixel_size}. DTM supports 8, 16 and 32 bit pixels")
+            }
+            DecodeError::InvalidSampleFormat => f.write_str(
+                "The encoded header contains an invalid sample format. Floating point pixels have to be 32 bit",
+            ),
+            DecodeError::InvalidDimensions { width, height } => {
+                write!(f, "The encoded header contains dimensions of {width}x{height}, which exceed the addressable memory or the encoded channels.")
+            }
+            DecodeError::InvalidChannels => f.write_str(
+                "The size table contains an invalid channels value. DTM supports 1 to 4 channels",
+            ),
+            DecodeError::InvalidChannelSize { channel, size } => {
+                write!(f, "The size table contains a size of {size} bytes for channel {channel}, which is larger than the uncompressed channel.")
+            }
+            DecodeError::InsufficientChannelData { channel, offset } => {
+                write!(
+                    f,
+                    "The input buffer ends after {offset} bytes of channel {channel}."
+                )
+            }
+            DecodeError::CorruptData { channel, offset } => {
+                write!(
+                    f,
+                    "The encoded data of channel {channel} is malformed at offset {offset}."
+                )
+            }
+            DecodeError::InvalidChunkSection => {
+                f.write_str("The chunk section of the header is malformed.")
+            }
+            DecodeError::UnsupportedChunk(id) => {
+                write!(f, "The image contains the unsupported critical chunk {id}.")
+            }
+            DecodeError::InvalidChunk(id) => {
+                write!(f, "The data of the chunk {id} is malformed.")
+            }
+            DecodeError::ChecksumMismatch { channel } => {
+                write!(
+                    f,
+                    "The encoded data of channel {channel} does not match its checksum."
+                )
+            }
+            DecodeError::SampleTypeMismatch => {
+                f.write_str("The image does not contain the requested pixel type.")
+            }
+            DecodeError::InvalidTiling => {
+                f.write_str("The tile size or the tile offset table in the header is invalid.")
+            }
+            DecodeError::InvalidRegion => {
+                f.write_str("The requested region exceeds the bounds of the image.")
+            }
+            DecodeError::InsufficientOutputBuffer => {
+                f.write_str("Output buffer is too small to fit the decoded image")
+            }
+            DecodeError::InvalidStride => {
+                f.write_str("The row stride is smaller than a row of the decoded image.")
+            }
+            DecodeError::InvalidTexelLayout => {
+                f.write_str("The texel layout has fewer channels than the image.")
+            }
+            DecodeError::OutOfMemory => {
+                f.write_str("The memory for the decoded image could not be allocated.")
+            }
+            DecodeError::IoError(error) => {
+                write!(
+                    f,
+                    "There occurred an io error while loading the image: {error}"
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(error: io::Error) -> Self {
+        DecodeError::IoError(error)
+    }
+}
+
+impl DTM {
+    /// Reads header from encoded DTM image.
+    ///
+    /// Both the versioned header and the legacy header of images written by version 0.1 are supported.
+    /// The legacy header stores the pixel size in place of the zero byte marking the versioned header.
+    pub(crate) fn decode_header(encoded: &[u8]) -> Result<Header, DecodeError> {
+        Self::decode_header_sections(encoded, true)
+    }
+
+    /// Reads the header up to the start of its chunks, which are neither read nor validated.
+    ///
+    /// The channel sizes cannot be checked against the validity masks,
+    /// thus a header accepted here may still be rejected by [`DTM::decode_header`].
+    /// The chunk range of the result lies beyond the end of `encoded`.
+    pub(crate) fn decode_header_without_chunks(encoded: &[u8]) -> Result<Header, DecodeError> {
+        Self::decode_header_sections(encoded, false)
+    }
+
+    /// Reads the header, including the chunks of its chunk section if `chunks` is set.
+    fn decode_header_sections(encoded: &[u8], chunks: bool) -> Result<Header, DecodeError> {
+        let magic = if let Some(magic) = encoded.get(..4) {
+            magic
+        } else {
+            return Err(DecodeError::InsufficientHeaderData);
+        };
+
+        if &magic[0..3] != DTM_MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+
+        if magic[3] == 0 {
+            Self::decode_versioned_header(encoded, chunks)
+        } else {
+            Self::decode_legacy_header(encoded)
+        }
+    }
+
+    /// Reads the versioned header.
+    ///
+    /// ```text
+    /// magic "dtm" | 0 | version | pixel size | sample format | channel count |
+    /// feature flags (u32) | width (u32) | height (u32) | channel sizes (4 x u32) |
+    /// channel checksums (channel count x u32, optional) |
+    /// tile width (u32, optional) | tile height (u32, optional) |
+    /// tile offsets (channel count x tile count x u32, optional) |
+    /// chunk section size (u32, optional) | chunks (optional)
+    /// ```
+    fn decode_versioned_header(encoded: &[u8], read_chunks: bool) -> Result<Header, DecodeError> {
+        let header = if let Some(header) = encoded.get(..DTM_HEADER_SIZE) {
+            header
+        } else {
+            return Err(DecodeError::InsufficientHeaderData);
+        };
+
+        let version = header[4];
+
+        if version == 0 || version > DTM_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let pixel_size = header[5] as u32;
+        let sample_format = decode_sample_format(header[6])?;
+        let channel_count = header[7] as u32;
+        let features = u32::from_be_bytes(header[8..12].try_into().unwrap());
+        let width = u32::from_be_bytes(header[12..16].try_into().unwrap());
+        let height = u32::from_be_bytes(header[16..20].try_into().unwrap());
+
+        if features & !SUPPORTED_FEATURES != 0 {
+            return Err(DecodeError::UnsupportedFeatures(
+                features & !SUPPORTED_FEATURES,
+            ));
+        }
+
+        if channel_count > 4 {
+            return Err(DecodeError::InvalidChannels);
+        }
+
+        let header_size = header_size(encoded);
+        let read_size = match read_chunks {
+            true => header_size,
+            false => header_size_without_chunks(encoded),
+        };
+
+        let header = if let Some(header) = encoded.get(..read_size) {
+            header
+        } else {
+            return Err(DecodeError::InsufficientHeaderData);
+        };
+
+        let descriptor = DTM {
+            pixel_size,
+            sample_format,
+            channel_count,
+            width,
+            height,
+        };
+
+        validate_descriptor(&descriptor)?;
+
+        let mut channel_sizes = [0; 4];
+        let mut total_size = header_size;
+
+        for (channel, size) in header[20..36].chunks_exact(4).enumerate() {
+            let channel_size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+
+            if channel >= channel_count as usize {
+                if channel_size != 0 {
+                    return Err(DecodeError::InvalidChannels);
+                }
+
+                continue;
+            }
+
+            if channel_size > descriptor.channel_size() {
+                return Err(DecodeError::InvalidChannelSize {
+                    channel,
+                    size: channel_size,
+                });
+            }
+
+            channel_sizes[channel] = channel_size;
+            total_size += channel_size;
+        }
+
+        let mut offset = DTM_HEADER_SIZE;
+
+        let checksums = (features & FEATURE_CHECKSUMS != 0).then(|| {
+            let mut checksums = [0; 4];
+
+            for checksum in &mut checksums[..channel_count as usize] {
+                *checksum = u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+                offset += 4;
+            }
+
+            checksums
+        });
+
+        let tiles = if features & FEATURE_TILES != 0 {
+            let tile_width = u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+            let tile_height =
+                u32::from_be_bytes(header[offset + 4..offset + 8].try_into().unwrap());
+            offset += 8;
+
+            if tile_width == 0 || tile_height == 0 {
+                return Err(DecodeError::InvalidTiling);
+            }
+
+            let tiling = Tiling::new(&descriptor, tile_width, tile_height);
+            let table_size = 4 * channel_count as usize * tiling.tile_count();
+            let table = &header[offset..offset + table_size];
+            offset += table_size;
+
+            Some(Tiles::decode(table, tiling, channel_sizes)?)
+        } else {
+            None
+        };
+
+        let chunks = if features & FEATURE_CHUNKS != 0 {
+            offset + 4..header_size
+        } else {
+            header_size..header_size
+        };
+
+        let coding = Coding {
+            predictors: features & FEATURE_PREDICTORS != 0,
+            entropy: features & FEATURE_ENTROPY != 0,
+        };
+
+        if read_chunks {
+            Chunks::new(&header[chunks.clone()]).validate()?;
+
+            let chunk_section = Chunks::new(&header[chunks.clone()]);
+            validate_channel_sizes(&descriptor, &channel_sizes, coding, chunk_section)?;
+        }
+
+        Ok(Header {
+            descriptor,
+            channel_sizes,
+            checksums,
+            tiles,
+            chunks,
+            coding,
+            inter_channel: features & FEATURE_INTER_CHANNEL != 0,
+            header_size,
+            total_size,
+        })
+    }
+
+    /// Reads the legacy header.
+    ///
+    /// ```text
+    /// magic "dtm" | pixel size | width (u32) | height (u32) | channel sizes (4 x u32)
+    /// ```
+    ///
+    /// The channel count is implied by the number of non-zero channel sizes.
+    /// Version 0.1 only wrote unsigned samples, so the whole byte after the magic is the pixel size
+    /// and a sample format can only be stored in the versioned header.
+    fn decode_legacy_header(encoded: &[u8]) -> Result<Header, DecodeError> {
+        let header = if let Some(header) = encoded.get(..DTM_LEGACY_HEADER_SIZE) {
+            header
+        } else {
+            return Err(DecodeError::InsufficientHeaderData);
+        };
+
+        let pixel_size = header[3] as u32;
+        let width = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let height = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+        let mut descriptor = DTM {
+            pixel_size,
+            sample_format: SampleFormat::Unsigned,
+            channel_count: 0,
+            width,
+            height,
+        };
+
+        validate_descriptor(&descriptor)?;
+
+        let mut channel_sizes = [0; 4];
+        let mut total_size = DTM_LEGACY_HEADER_SIZE;
+
+        for (channel, size) in header[12..28].chunks_exact(4).enumerate() {
+            let channel_size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+
+            if channel_size == 0 {
+                continue;
+            }
+
+            if channel != descriptor.channel_count as usize {
+                return Err(DecodeError::InvalidChannels);
+            }
+
+            if channel_size > descriptor.channel_size() {
+                return Err(DecodeError::InvalidChannelSize {
+                    channel,
+                    size: channel_size,
+                });
+            }
+
+            channel_sizes[channel] = channel_size;
+            total_size += channel_size;
+            descriptor.channel_count += 1;
+        }
+
+        validate_channel_sizes(
+            &descriptor,
+            &channel_sizes,
+            Coding::default(),
+            Chunks::new(&[]),
+        )?;
+
+        Ok(Header {
+            descriptor,
+            channel_sizes,
+            checksums: None,
+            tiles: None,
+            chunks: DTM_LEGACY_HEADER_SIZE..DTM_LEGACY_HEADER_SIZE,
+            coding: Coding::default(),
+            inter_channel: false,
+            header_size: DTM_LEGACY_HEADER_SIZE,
+            total_size,
+        })
+    }
+
+    /// Decodes a DTM image from a file into a newly allocated `Vec`.
+    #[inline]
+    pub fn decode_file<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<u8>), DecodeError> {
+        let encoded = fs::read(path)?;
+
+        DTM::decode_alloc(&encoded)
+    }
+
+    /// Decodes a DTM image and its metadata from a file into a newly allocated `Vec`.
+    #[inline]
+    pub fn decode_file_with_metadata<P: AsRef<Path>>(
+        path: P,
+    ) -> Result<(Self, Metadata, Vec<u8>), DecodeError> {
+        let encoded = fs::read(path)?;
+
+        DTM::decode_alloc_with_metadata(&encoded)
+    }
+
+    /// Decodes a DTM image from a byte slice into the `decoded` slice.
+    ///
+    /// Masked pixels are set to the nodata value of their channel, or zero if it has none.
+    #[inline]
+    pub fn decode(encoded: &[u8], decoded: &mut [u8]) -> Result<Self, DecodeError> {
+        Self::decode_with_masks(encoded, decoded, None, TexelLayout::Channels)
+            .map(|(descriptor, _)| descriptor)
+    }
+
+    /// Decodes a DTM image from a byte slice into a window of the larger `decoded` slice.
+    ///
+    /// The first row of the image is written at `offset` and the rows are `row_stride` bytes apart,
+    /// so that e.g. a tile can be decoded straight into a mosaic.
+    /// The bytes between the rows are left unchanged.
+    #[inline]
+    pub fn decode_strided(
+        encoded: &[u8],
+        decoded: &mut [u8],
+        offset: usize,
+        row_stride: usize,
+    ) -> Result<Self, DecodeError> {
+        let decoded = decoded.get_mut(offset..).unwrap_or_default();
+
+        Self::decode_with_masks(encoded, decoded, Some(row_stride), TexelLayout::Channels)
+            .map(|(descriptor, _)| descriptor)
+    }
+
+    /// Decodes a DTM image from a byte slice into a buffer ready to be uploaded into a texture.
+    ///
+    /// The rows are `row_pitch` bytes apart, e.g. a multiple of 256 as texture copies of wgpu require,
+    /// and the bytes between them are left unchanged.
+    /// The pixels are expanded to the texel layout, whose additional channels are set to zero.
+    pub fn decode_texture(
+        encoded: &[u8],
+        decoded: &mut [u8],
+        row_pitch: usize,
+        layout: TexelLayout,
+    ) -> Result<Self, DecodeError> {
+        Self::decode_with_masks(encoded, decoded, Some(row_pitch), layout)
+            .map(|(descriptor, _)| descriptor)
+    }
+
+    /// Decodes a DTM image from a byte slice into the `decoded` slice
+    /// and returns the validity masks of the channels.
+    ///
+    /// The rows are `row_stride` bytes apart, or stored back to back if it is `None`,
+    /// and the pixels are expanded to the texel layout.
+    fn decode_with_masks(
+        encoded: &[u8],
+        decoded: &mut [u8],
+        row_stride: Option<usize>,
+        layout: TexelLayout,
+    ) -> Result<(Self, Masks), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let Header {
+            descriptor,
+            channel_sizes,
+            header_size,
+            total_size,
+            ..
+        } = header;
+
+        let chunks = Chunks::new(&encoded[header.chunks.clone()]);
+
+        let encoded = match encoded.get(header_size..total_size) {
+            Some(encoded) => encoded,
+            None => {
+                return Err(Self::insufficient_channel_data(
+                    &channel_sizes,
+                    encoded.len() - header_size,
+                ))
+            }
+        };
+
+        let texel_channels = layout.channel_count(descriptor.channel_count);
+
+        if texel_channels < descriptor.channel_count {
+            return Err(DecodeError::InvalidTexelLayout);
+        }
+
+        let texel_channels = texel_channels as usize;
+        let row_size = descriptor.pixel_size as usize * texel_channels * descriptor.width as usize;
+        let row_stride = row_stride.unwrap_or(row_size);
+
+        if row_stride < row_size {
+            return Err(DecodeError::InvalidStride);
+        }
+
+        let decoded = match descriptor
+            .strided_size(row_size, row_stride)
+            .and_then(|size| decoded.get_mut(..size))
+        {
+            Some(decoded) => decoded,
+            None => return Err(DecodeError::InsufficientOutputBuffer),
+        };
+
+        // The masks are only decoded once the image is known to fit into the output buffer.
+        let mut masks = decode_masks(chunks, &descriptor)?;
+
+        with_pixel!(descriptor, P => {
+            if row_stride == descriptor.row_size() && texel_channels == descriptor.channel_count as usize {
+                decode_interleaved::<P, u8>(&header, encoded, decoded, &mut masks)?
+            } else {
+                let texels = (row_stride, texel_channels);
+                decode_strided::<P>(&header, encoded, decoded, texels, &mut masks)?
+            }
+        });
+
+        Ok((descriptor, masks))
+    }
+
+    /// Returns the error for encoded data, which ends after `size` bytes of the channel data.
+    fn insufficient_channel_data(channel_sizes: &[usize; 4], mut size: usize) -> DecodeError {
+        let mut channel = 0;
+
+        while size >= channel_sizes[channel] {
+            size -= channel_sizes[channel];
+            channel += 1;
+        }
+
+        DecodeError::InsufficientChannelData {
+            channel,
+            offset: size,
+        }
+    }
+
+    /// Decodes a DTM image from a byte slice into a newly allocated `Vec`.
+    #[inline]
+    pub fn decode_alloc(encoded: &[u8]) -> Result<(Self, Vec<u8>), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let mut decoded = alloc_decoded(&header, encoded, 0, header.descriptor.image_size())?;
+        let descriptor = Self::decode(encoded, &mut decoded)?;
+
+        Ok((descriptor, decoded))
+    }
+
+    /// Decodes a DTM image from a byte slice into a newly allocated `Vec`
+    /// and returns the validity mask of every channel, which has one.
+    ///
+    /// A mask contains `false` for every masked pixel,
+    /// which is set to the nodata value of its channel, or zero if it has none.
+    pub fn decode_alloc_with_masks(
+        encoded: &[u8],
+    ) -> Result<(Self, Vec<u8>, ValidityMasks), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let mut decoded = alloc_decoded(&header, encoded, 0, header.descriptor.image_size())?;
+        let (descriptor, masks) =
+            Self::decode_with_masks(encoded, &mut decoded, None, TexelLayout::Channels)?;
+
+        let masks = masks
+            .into_iter()
+            .take(descriptor.channel_count as usize)
+            .map(|mask| mask.map(|mask| mask.valid))
+            .collect();
+
+        Ok((descriptor, decoded, masks))
+    }
+
+    /// Decodes the pixels inside a region of a DTM image from a byte slice into the `decoded` slice.
+    ///
+    /// The region is returned pixel-interleaved and row by row like a whole image.
+    /// Only the tiles intersecting the region are decoded, if the image is tiled
+    /// (see [`Encoder::tiles`](crate::encode::Encoder::tiles)).
+    /// Otherwise, the image is decoded row by row, while only the region is kept.
+    ///
+    /// Checksums are not verified, as the encoded data of the other tiles is not read.
+    pub fn decode_region(
+        encoded: &[u8],
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        decoded: &mut [u8],
+    ) -> Result<Self, DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let descriptor = header.descriptor;
+
+        // The slice has to contain all channels, so that the row buffers of untiled images
+        // are only allocated for dimensions, which the encoded data can hold.
+        if encoded.len() < header.total_size {
+            return Err(Self::insufficient_channel_data(
+                &header.channel_sizes,
+                encoded.len() - header.header_size,
+            ));
+        }
+
+        if x as u64 + width as u64 > descriptor.width as u64
+            || y as u64 + height as u64 > descriptor.height as u64
+        {
+            return Err(DecodeError::InvalidRegion);
+        }
+
+        let region = Rect {
+            x: x as usize,
+            y: y as usize,
+            width: width as usize,
+            height: height as usize,
+        };
+
+        let region_size = region.pixel_count()
+            * descriptor.pixel_size as usize
+            * descriptor.channel_count as usize;
+
+        let decoded = match decoded.get_mut(..region_size) {
+            Some(decoded) => decoded,
+            None => return Err(DecodeError::InsufficientOutputBuffer),
+        };
+
+        // Empty regions are returned right away, as rows without pixels would still be read.
+        if region.pixel_count() == 0 {
+            return Ok(descriptor);
+        }
+
+        match &header.tiles {
+            Some(tiles) => with_pixel!(descriptor, P => {
+                decode_region_tiles::<P>(&header, tiles, encoded, region, decoded)?
+            }),
+            None => decode_region_rows(&descriptor, encoded, region, decoded)?,
+        }
+
+        Ok(descriptor)
+    }
+
+    /// Decodes a range of rows of a DTM image from a byte slice into the `decoded` slice.
+    ///
+    /// Only the row groups containing the rows are decoded, if the image has restart points
+    /// (see [`Encoder::row_groups`](crate::encode::Encoder::row_groups)).
+    /// Like for [`DTM::decode_region`], checksums are not verified.
+    pub fn decode_rows(
+        encoded: &[u8],
+        rows: Range<u32>,
+        decoded: &mut [u8],
+    ) -> Result<Self, DecodeError> {
+        let header = Self::decode_header(encoded)?;
+
+        if rows.start > rows.end {
+            return Err(DecodeError::InvalidRegion);
+        }
+
+        let width = header.descriptor.width;
+
+        Self::decode_region(encoded, 0, rows.start, width, rows.len() as u32, decoded)
+    }
+
+    /// Decodes a DTM image and its metadata from a byte slice into a newly allocated `Vec`.
+    #[inline]
+    pub fn decode_alloc_with_metadata(
+        encoded: &[u8],
+    ) -> Result<(Self, Metadata, Vec<u8>), DecodeError> {
+        let (_, metadata) = Self::decode_metadata(encoded)?;
+        let (descriptor, decoded) = Self::decode_alloc(encoded)?;
+
+        Ok((descriptor, metadata, decoded))
+    }
+
+    /// Decodes a DTM image from a byte slice into physical values in a newly allocated `Vec`.
+    ///
+    /// The pixel values of each channel are mapped using the [`Scale`]
+    /// stored for it, while they are decoded.
+    /// Channels without a scale keep their values and masked pixels are decoded as NaN.
+    pub fn decode_f32_alloc(encoded: &[u8]) -> Result<(Self, Vec<f32>), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let metadata = Metadata::decode(Chunks::new(&encoded[header.chunks.clone()]))?;
+        let descriptor = header.descriptor;
+
+        // The preceding channel is needed as integers to restore the differences to it,
+        // so the pixels are decoded before they are mapped.
+        if header.inter_channel {
+            let mut pixels = alloc_decoded(&header, encoded, 0, descriptor.image_size())?;
+            let (_, masks) =
+                Self::decode_with_masks(encoded, &mut pixels, None, TexelLayout::Channels)?;
+
+            let channel_count = descriptor.channel_count as usize;
+            let scales: Vec<_> = metadata.scales[..channel_count]
+                .iter()
+                .map(|scale| scale.clone().unwrap_or_default())
+                .collect();
+
+            let decoded = with_pixel!(descriptor, P => {
+                pixels
+                    .chunks_exact(P::SIZE)
+                    .enumerate()
+                    .map(|(index, pixel)| {
+                        let channel = index % channel_count;
+                        let &Scale { scale, offset, .. } = &scales[channel];
+
+                        match &masks[channel] {
+                            Some(mask) if !mask.valid[index / channel_count] => f32::NAN,
+                            _ => (P::read(pixel).value() * scale + offset) as f32,
+                        }
+                    })
+                    .collect()
+            });
+
+            return Ok((descriptor, decoded));
+        }
+
+        let mut masks = decode_masks(Chunks::new(&encoded[header.chunks.clone()]), &descriptor)?;
+
+        let encoded = match encoded.get(header.header_size..header.total_size) {
+            Some(encoded) => encoded,
+            None => {
+                return Err(Self::insufficient_channel_data(
+                    &header.channel_sizes,
+                    encoded.len() - header.header_size,
+                ))
+            }
+        };
+
+        let pixel_count = descriptor.channel_count as usize * descriptor.pixel_count();
+        let mut decoded = try_vec(0.0, pixel_count)?;
+
+        with_pixel!(descriptor, P => {
+            let mut decoded = Decoded::<P, _>::new(
+                descriptor.width as usize,
+                descriptor.height as usize,
+                Physical {
+                    row: RowRing::new(descriptor.width as usize)?,
+                    data: &mut decoded,
+                    channel_count: descriptor.channel_count as usize,
+                    channel: 0,
+                    scale: 1.0,
+                    offset: 0.0,
+                },
+            );
+
+            decode_channels(&header, encoded, &mut decoded, &mut masks, |target, channel| {
+                let scale = metadata.scales[channel].clone().unwrap_or_default();
+
+                target.channel = channel;
+                target.scale = scale.scale;
+                target.offset = scale.offset;
+            })?
+        });
+
+        Ok((descriptor, decoded))
+    }
+
+    /// Reads the descriptor and the metadata of an encoded DTM image without decoding any pixels.
+    ///
+    /// Only the header of the image has to be present in `encoded`.
+    pub fn decode_metadata(encoded: &[u8]) -> Result<(Self, Metadata), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let metadata = Metadata::decode(Chunks::new(&encoded[header.chunks]))?;
+
+        Ok((header.descriptor, metadata))
+    }
+
+    /// Reads the metadata chunks of an encoded DTM image without decoding any pixels.
+    ///
+    /// Only the header of the image has to be present in `encoded`.
+    pub fn read_chunks(encoded: &[u8]) -> Result<Vec<Chunk>, DecodeError> {
+        let header = Self::decode_header(encoded)?;
+
+        Chunks::new(&encoded[header.chunks]).into_vec()
+    }
+
+    /// Validates an encoded DTM image without storing the decoded pixels.
+    ///
+    /// The image is decoded row by row, which checks the structure of the encoded data
+    /// as well as the checksums of the channels, if the image stores them.
+    pub fn verify(encoded: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = DtmReader::new(encoded)?;
+        reader.read_rows(|_| {})?;
+
+        Ok(reader.descriptor())
+    }
+
+    /// Decodes a DTM image of unsigned 16 bit pixels from a byte slice into a newly allocated `Vec`.
+    pub fn decode_u16_alloc(encoded: &[u8]) -> Result<(Self, Vec<u16>), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        check_samples(&header.descriptor, SampleFormat::Unsigned)?;
+
+        let mut decoded = alloc_decoded(&header, encoded, 0, header.descriptor.image_size() / 2)?;
+        Self::decode_samples::<u16, u16>(&header, encoded, &mut decoded)?;
+
+        Ok((header.descriptor, decoded))
+    }
+
+    /// Decodes a DTM image of unsigned 16 bit pixels from a byte slice into the `decoded` slice.
+    ///
+    /// Masked pixels are set to the nodata value of their channel, or zero if it has none.
+    pub fn decode_into_u16(encoded: &[u8], decoded: &mut [u16]) -> Result<Self, DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        check_samples(&header.descriptor, SampleFormat::Unsigned)?;
+
+        Self::decode_samples::<u16, u16>(&header, encoded, decoded)?;
+
+        Ok(header.descriptor)
+    }
+
+    /// Decodes a DTM image of unsigned 16 bit pixels from a byte slice into a plane per channel.
+    ///
+    /// Masked pixels are set to the nodata value of their channel, or zero if it has none.
+    pub fn decode_planar(encoded: &[u8]) -> Result<(Self, Vec<Vec<u16>>), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        let Header {
+            descriptor,
+            channel_sizes,
+            header_size,
+            total_size,
+            ..
+        } = header;
+
+        check_samples(&descriptor, SampleFormat::Unsigned)?;
+
+        let chunks = Chunks::new(&encoded[header.chunks.clone()]);
+
+        let encoded = match encoded.get(header_size..total_size) {
+            Some(encoded) => encoded,
+            None => {
+                return Err(Self::insufficient_channel_data(
+                    &channel_sizes,
+                    encoded.len() - header_size,
+                ))
+            }
+        };
+
+        let mut masks = decode_masks(chunks, &descriptor)?;
+        let mut planes = decode_planes::<u16, u16>(&header, encoded, &mut masks)?;
+
+        for (plane, mask) in planes.iter_mut().zip(&masks) {
+            if let Some(mask) = mask {
+                fill_channel::<u16, u16>(plane, mask, 0, 1, 0);
+            }
+        }
+
+        if header.inter_channel {
+            for channel in 1..planes.len() {
+                let (references, planes) = planes.split_at_mut(channel);
+                let mask = masks[channel].as_ref();
+                restore_plane::<u16, u16>(&mut planes[0], &references[channel - 1], mask, 0);
+            }
+        }
+
+        Ok((descriptor, planes))
+    }
+
+    /// Decodes a DTM image of signed 16 bit pixels from a byte slice into a newly allocated `Vec`.
+    pub fn decode_i16(encoded: &[u8]) -> Result<(Self, Vec<i16>), DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        check_samples(&header.descriptor, SampleFormat::Signed)?;
+
+        let mut decoded = alloc_decoded(&header, encoded, 0, header.descriptor.image_size() / 2)?;
+        Self::decode_samples::<Signed<u16>, i16>(&header, encoded, &mut decoded)?;
+
+        Ok((header.descriptor, decoded))
+    }
+
+    /// Decodes a DTM image of signed 16 bit pixels from a byte slice into the `decoded` slice.
+    ///
+    /// Masked pixels are set to the nodata value of their channel, or zero if it has none.
+    pub fn decode_into_i16(encoded: &[u8], decoded: &mut [i16]) -> Result<Self, DecodeError> {
+        let header = Self::decode_header(encoded)?;
+        check_samples(&header.descriptor, SampleFormat::Signed)?;
+
+        Self::decode_samples::<Signed<u16>, i16>(&header, encoded, decoded)?;
+
+        Ok(header.descriptor)
+    }
+
+    /// Decodes the image, whose header has been read and whose pixels are of type `P`,
+    /// straight into the typed samples of the `decoded` slice.
+    fn decode_samples<P: Pixel, E: Element<P>>(
+        header: &Header,
+        encoded: &[u8],
+        decoded: &mut [E],
+    ) -> Result<(), DecodeError> {
+        let descriptor = &header.descriptor;
+        let len = descriptor.image_size() / P::SIZE * E::PER_PIXEL;
+
+        let decoded = match decoded.get_mut(..len) {
+            Some(decoded) => decoded,
+            None => return Err(DecodeError::InsufficientOutputBuffer),
+        };
+
+        let chunks = Chunks::new(&encoded[header.chunks.clone()]);
+
+        let encoded = match encoded.get(header.header_size..header.total_size) {
+            Some(encoded) => encoded,
+            None => {
+                return Err(Self::insufficient_channel_data(
+                    &header.channel_sizes,
+                    encoded.len() - header.header_size,
+                ))
+            }
+        };
+
+        let mut masks = decode_masks(chunks, descriptor)?;
+        decode_interleaved::<P, E>(header, encoded, decoded, &mut masks)
+    }
+}
+
+/// Returns the size of the header including the sections enabled by its feature flags,
+/// as far as it can be determined from the start of the header.
+///
+/// If the result exceeds the length of `header`, it has to be called again
+/// with the additional bytes, until the size is known.
+/// The header has to contain at least the magic value.
+pub(crate) fn header_size(header: &[u8]) -> usize {
+    let size = header_size_without_chunks(header);
+    let chunks = header[3] == 0
+        && header.get(8..12).is_some_and(|features| {
+            u32::from_be_bytes(features.try_into().unwrap()) & FEATURE_CHUNKS != 0
+        });
+
+    match header.get(size.saturating_sub(4)..size) {
+        Some(bytes) if chunks => {
+            size.saturating_add(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        }
+        _ => size,
+    }
+}
+
+/// Returns the size of the header up to and including the size of its chunk section,
+/// as far as it can be determined from the start of the header.
+///
+/// It has to be called again with the additional bytes like [`header_size`].
+pub(crate) fn header_size_without_chunks(header: &[u8]) -> usize {
+    if header[3] != 0 {
+        return DTM_LEGACY_HEADER_SIZE;
+    }
+
+    if header.len() < DTM_HEADER_SIZE {
+        return DTM_HEADER_SIZE;
+    }
+
+    let channel_count = header[7].min(4) as usize;
+    let features = u32::from_be_bytes(header[8..12].try_into().unwrap());
+
+    let mut size = DTM_HEADER_SIZE;
+
+    if features & FEATURE_CHECKSUMS != 0 {
+        size += 4 * channel_count;
+    }
+
+    if features & FEATURE_TILES != 0 {
+        size += 8;
+
+        let tile_size = match header.get(size - 8..size) {
+            Some(bytes) => bytes,
+            None => return size,
+        };
+
+        let tile_width = u32::from_be_bytes(tile_size[0..4].try_into().unwrap()) as usize;
+        let tile_height = u32::from_be_bytes(tile_size[4..8].try_into().unwrap()) as usize;
+        let width = u32::from_be_bytes(header[12..16].try_into().unwrap()) as usize;
+        let height = u32::from_be_bytes(header[16..20].try_into().unwrap()) as usize;
+
+        // Invalid tile sizes are rejected once the header is parsed.
+        if tile_width != 0 && tile_height != 0 {
+            let tile_count = width
+                .div_ceil(tile_width)
+                .saturating_mul(height.div_ceil(tile_height));
+
+            size = size.saturating_add(tile_count.saturating_mul(4 * channel_count));
+        }
+    }
+
+    if features & FEATURE_CHUNKS != 0 {
+        size = size.saturating_add(4);
+    }
+
+    size
+}
+
+/// Parses the sample format stored in the header.
+fn decode_sample_format(value: u8) -> Result<SampleFormat, DecodeError> {
+    match value {
+        0 => Ok(SampleFormat::Unsigned),
+        1 => Ok(SampleFormat::Float),
+        2 => Ok(SampleFormat::Signed),
+        _ => Err(DecodeError::InvalidSampleFormat),
+    }
+}
+
+/// Ensures that the pixel type is supported and that the image size is addressable.
+fn validate_descriptor(descriptor: &DTM) -> Result<(), DecodeError> {
+    let DTM {
+        pixel_size,
+        sample_format,
+        width,
+        height,
+        ..
+    } = *descriptor;
+
+    if !matches!(pixel_size, 1 | 2 | 4) {
+        return Err(DecodeError::InvalidPixelSize(pixel_size));
+    }
+
+    if !sample_format.supports(pixel_size) {
+        return Err(DecodeError::InvalidSampleFormat);
+    }
+
+    // The image size has to be addressable, so that it can be computed without overflowing.
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|size| size.checked_mul(4 * pixel_size as usize))
+        .ok_or(DecodeError::InvalidDimensions { width, height })?;
+
+    Ok(())
+}
+
+/// The largest number of pixels, which an opcode encodes, i.e. the longest run.
+const MAX_PIXELS_PER_OPCODE: usize = 63;
+
+/// Ensures that the encoded channels can hold the pixels of the image.
+///
+/// Every byte of a compressed channel encodes a limited number of pixels and masked pixels
+/// are not encoded at all, so that malformed dimensions are rejected before anything is allocated.
+fn validate_channel_sizes(
+    descriptor: &DTM,
+    channel_sizes: &[usize; 4],
+    coding: Coding,
+    chunks: Chunks<'_>,
+) -> Result<(), DecodeError> {
+    let pixel_count = descriptor.pixel_count();
+    let mut masked = [0; 4];
+
+    for chunk in chunks {
+        let (id, data) = chunk?;
+
+        if id == ChunkId::MASK {
+            let (channel, count) = ChannelMask::masked_count(data, pixel_count)?;
+            masked[channel] = count;
+        }
+    }
+
+    let pixels_per_byte = match coding.entropy {
+        true => rice::MAX_PIXELS_PER_BYTE,
+        false => MAX_PIXELS_PER_OPCODE,
+    };
+
+    for channel in 0..descriptor.channel_count as usize {
+        if pixel_count - masked[channel] > channel_sizes[channel].saturating_mul(pixels_per_byte) {
+            return Err(DecodeError::InvalidDimensions {
+                width: descriptor.width,
+                height: descriptor.height,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Allocates `len` elements for the decoded image, once the encoded data is known to be complete.
+///
+/// As the header only declares as many pixels as its channels can hold,
+/// the allocation is bounded by the size of the encoded data, apart from masked pixels.
+fn alloc_decoded<T: Clone>(
+    header: &Header,
+    encoded: &[u8],
+    value: T,
+    len: usize,
+) -> Result<Vec<T>, DecodeError> {
+    if encoded.len() < header.total_size {
+        return Err(DTM::insufficient_channel_data(
+            &header.channel_sizes,
+            encoded.len() - header.header_size,
+        ));
+    }
+
+    try_vec(value, len)
+}
+
+/// Checks that the pixels of the image are 16 bit integers of the given sample format.
+fn check_samples(descriptor: &DTM, sample_format: SampleFormat) -> Result<(), DecodeError> {
+    if descriptor.pixel_size != 2 || descriptor.sample_format != sample_format {
+        return Err(DecodeError::SampleTypeMismatch);
+    }
+
+    Ok(())
+}
+
+/// Allocates `len` elements, whose number is derived from the header,
+/// and returns an error instead of aborting if the memory is exhausted.
+pub(crate) fn try_vec<T: Clone>(value: T, len: usize) -> Result<Vec<T>, DecodeError> {
+    let mut data = Vec::new();
+
+    if data.try_reserve_exact(len).is_err() {
+        return Err(DecodeError::OutOfMemory);
+    }
+
+    data.resize(len, value);
+
+    Ok(data)
+}
+
+/// Decodes the tiles intersecting the region into the pixel-interleaved `decoded` slice.
+fn decode_region_tiles<P: Pixel>(
+    header: &Header,
+    tiles: &Tiles,
+    encoded: &[u8],
+    region: Rect,
+    decoded: &mut [u8],
+) -> Result<(), DecodeError> {
+    let descriptor = &header.descriptor;
+    let width = descriptor.width as usize;
+    let channel_count = descriptor.channel_count as usize;
+    let masks = decode_masks(Chunks::new(&encoded[header.chunks.clone()]), descriptor)?;
+
+    let tiling = &tiles.tiling;
+    let mut tile_data = try_vec(0, tiling.largest_tile().pixel_count() * P::SIZE)?;
+    let mut channel_start = header.header_size;
+
+    for (channel, mask) in masks.iter().enumerate().take(channel_count) {
+        for tile in tiling.tiles(region) {
+            let rect = tiling.rect(tile);
+            let range = tiles.range(channel, tile);
+            let tile_data = &mut tile_data[..rect.pixel_count() * P::SIZE];
+
+            let data = match encoded.get(channel_start + range.start..channel_start + range.end) {
+                Some(data) => data,
+                None => {
+                    return Err(DecodeError::InsufficientChannelData {
+                        channel,
+                        offset: encoded.len() - channel_start.min(encoded.len()),
+                    })
+                }
+            };
+
+            let mut encoded = Encoded::new(data);
+            encoded.channel = channel;
+            encoded.offset = range.start;
+
+            let mask = mask.as_ref().map(|mask| mask.crop(&rect, width));
+            decode_tile::<P>(encoded, &rect, mask, header.coding, tile_data)?;
+
+            for (index, pixel) in tile_data.chunks_exact(P::SIZE).enumerate() {
+                let (x, y) = (rect.x + index % rect.width, rect.y + index / rect.width);
+
+                if (region.x..region.x + region.width).contains(&x)
+                    && (region.y..region.y + region.height).contains(&y)
+                {
+                    let index = ((y - region.y) * region.width + x - region.x) * channel_count;
+                    let index = (index + channel) * P::SIZE;
+                    decoded[index..index + P::SIZE].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        channel_start += header.channel_sizes[channel];
+    }
+
+    if header.inter_channel {
+        let row_size = region.width * channel_count * P::SIZE;
+
+        for (y, row) in decoded.chunks_mut(row_size.max(1)).enumerate() {
+            let start = (region.y + y) * width + region.x;
+            restore_channels::<P, u8>(row, &masks, channel_count, channel_count, start);
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes an untiled image row by row into the pixel-interleaved `decoded` slice,
+/// while only the pixels inside the region are kept.
+fn decode_region_rows(
+    descriptor: &DTM,
+    encoded: &[u8],
+    region: Rect,
+    decoded: &mut [u8],
+) -> Result<(), DecodeError> {
+    let pixel_size = descriptor.pixel_size as usize;
+    let channel_count = descriptor.channel_count as usize;
+
+    let mut reader = DtmReader::new(encoded)?;
+
+    reader.read_rows(|row| {
+        let y = row.y as usize;
+
+        if !(region.y..region.y + region.height).contains(&y) {
+            return;
+        }
+
+        let pixels = row.data[region.x * pixel_size..(region.x + region.width) * pixel_size]
+            .chunks_exact(pixel_size);
+
+        for (x, pixel) in pixels.enumerate() {
+            let index = ((y - region.y) * region.width + x) * channel_count + row.channel as usize;
+            decoded[index * pixel_size..(index + 1) * pixel_size].copy_from_slice(pixel);
+        }
+    })
+}
+
+/// Decodes the channels one after another into the pixel-interleaved `decoded` slice.
+///
+/// Afterwards, the masked pixels are set to the nodata values of their channels.
+fn decode_interleaved<P: Pixel, E: Element<P>>(
+    header: &Header,
+    encoded: &[u8],
+    decoded: &mut [E],
+    masks: &mut Masks,
+) -> Result<(), DecodeError> {
+    let descriptor = &header.descriptor;
+    let channel_count = descriptor.channel_count as usize;
+
+    #[cfg(feature = "parallel")]
+    if let (1, Some(tiles)) = (channel_count, &header.tiles) {
+        let data = &encoded[..header.channel_sizes[0]];
+        verify_checksum(header, 0, data)?;
+        let threads = tile_threads(descriptor);
+        decode_tiles_parallel::<P, E>(header, tiles, 0, data, decoded, masks[0].as_ref(), threads)?;
+        fill_nodata::<P, E>(decoded, masks, 1, 0);
+
+        return Ok(());
+    }
+
+    #[cfg(feature = "parallel")]
+    if channel_count > 1 {
+        decode_interleaved_parallel::<P, E>(header, encoded, decoded, masks)?;
+        fill_nodata::<P, E>(decoded, masks, channel_count, 0);
+
+        if header.inter_channel {
+            restore_channels::<P, E>(decoded, masks, channel_count, channel_count, 0);
+        }
+
+        return Ok(());
+    }
+
+    let mut target = Decoded::<P, _>::new(
+        descriptor.width as usize,
+        descriptor.height as usize,
+        Interleaved {
+            data: &mut *decoded,
+            channel_count,
+            channel: 0,
+        },
+    );
+
+    decode_channels(header, encoded, &mut target, masks, |target, channel| {
+        target.channel = channel;
+    })?;
+
+    fill_nodata::<P, E>(decoded, masks, channel_count, 0);
+
+    if header.inter_channel {
+        restore_channels::<P, E>(decoded, masks, channel_count, channel_count, 0);
+    }
+
+    Ok(())
+}
+
+/// Decodes the channels one after another into the pixel-interleaved `decoded` slice,
+/// whose rows are `row_stride` bytes apart and whose pixels have `texel_channels` channels.
+///
+/// Afterwards, the masked pixels are set to the nodata values of their channels
+/// and the channels missing in the image are set to zero.
+fn decode_strided<P: Pixel>(
+    header: &Header,
+    encoded: &[u8],
+    decoded: &mut [u8],
+    (row_stride, texel_channels): (usize, usize),
+    masks: &mut Masks,
+) -> Result<(), DecodeError> {
+    let descriptor = &header.descriptor;
+    let width = descriptor.width as usize;
+    let channel_count = descriptor.channel_count as usize;
+
+    let mut target = Decoded::<P, _>::new(
+        width,
+        descriptor.height as usize,
+        Strided {
+            data: &mut *decoded,
+            width,
+            row_stride,
+            channel_count: texel_channels,
+            channel: 0,
+        },
+    );
+
+    decode_channels(header, encoded, &mut target, masks, |target, channel| {
+        target.channel = channel;
+    })?;
+
+    for (y, row) in decoded.chunks_mut(row_stride.max(1)).enumerate() {
+        let row = &mut row[..width * texel_channels * P::SIZE];
+        fill_nodata::<P, u8>(row, masks, texel_channels, y * width);
+
+        if header.inter_channel {
+            restore_channels::<P, u8>(row, masks, channel_count, texel_channels, y * width);
+        }
+
+        if texel_channels > channel_count {
+            for texel in row.chunks_exact_mut(texel_channels * P::SIZE) {
+                texel[channel_count * P::SIZE..].fill(0);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Overwrites the masked pixels of the interleaved channels, starting with the pixel
+/// at index `start`, with their nodata values.
+fn fill_nodata<P: Pixel, E: Element<P>>(
+    decoded: &mut [E],
+    masks: &Masks,
+    channel_count: usize,
+    start: usize,
+) {
+    for (channel, mask) in masks.iter().enumerate() {
+        if let Some(mask) = mask {
+            fill_channel::<P, E>(decoded, mask, channel, channel_count, start);
+        }
+    }
+}
+
+/// Overwrites the masked pixels of a channel of `channel_count` interleaved channels,
+/// starting with the pixel at index `start`, with the nodata value of the channel.
+fn fill_channel<P: Pixel, E: Element<P>>(
+    decoded: &mut [E],
+    mask: &ChannelMask,
+    channel: usize,
+    channel_count: usize,
+    start: usize,
+) {
+    let nodata = P::read(&mask.nodata.unwrap_or(0).to_le_bytes());
+    let pixel_count = decoded.len() / (channel_count * E::PER_PIXEL);
+
+    for (index, &valid) in mask.valid[start..].iter().take(pixel_count).enumerate() {
+        if !valid {
+            E::set(decoded, index * channel_count + channel, nodata);
+        }
+    }
+}
+
+/// Adds the preceding channel to the valid pixels of every channel but the first
+/// of the interleaved pixels of `texel_channels` channels, starting with the pixel at index `start`.
+///
+/// The channels are restored in order, so the preceding channel is always restored already.
+/// Its masked pixels have to hold their nodata values.
+fn restore_channels<P: Pixel, E: Element<P>>(
+    decoded: &mut [E],
+    masks: &Masks,
+    channel_count: usize,
+    texel_channels: usize,
+    start: usize,
+) {
+    if channel_count < 2 {
+        return;
+    }
+
+    for index in 0..decoded.len() / (texel_channels * E::PER_PIXEL) {
+        let texel = index * texel_channels;
+
+        for (channel, mask) in masks.iter().enumerate().take(channel_count).skip(1) {
+            if mask.as_ref().is_some_and(|mask| !mask.valid[start + index]) {
+                continue;
+            }
+
+            let reference = E::get(decoded, texel + channel - 1);
+            let pixel = E::get(decoded, texel + channel);
+            E::set(
+                decoded,
+                texel + channel,
+                predictor::restore_channel(pixel, reference),
+            );
+        }
+    }
+}
+
+/// Adds the pixels of the preceding channel in `references` to the valid pixels of `decoded`,
+/// which belong to a channel starting with the pixel at index `start`.
+pub(crate) fn restore_plane<P: Pixel, E: Element<P>>(
+    decoded: &mut [E],
+    references: &[E],
+    mask: Option<&ChannelMask>,
+    start: usize,
+) {
+    let pixel_count = decoded.len().min(references.len()) / E::PER_PIXEL;
+
+    for index in 0..pixel_count {
+        if mask.is_some_and(|mask| !mask.valid[start + index]) {
+            continue;
+        }
+
+        let pixel = predictor::restore_channel(E::get(decoded, index), E::get(references, index));
+        E::set(decoded, index, pixel);
+    }
+}
+
+/// Decodes the channels on a thread each into planes of their own,
+/// which are interleaved into the `decoded` slice afterwards.
+#[cfg(feature = "parallel")]
+fn decode_interleaved_parallel<P: Pixel, E: Element<P>>(
+    header: &Header,
+    encoded: &[u8],
+    decoded: &mut [E],
+    masks: &mut Masks,
+) -> Result<(), DecodeError> {
+    let channel_count = header.descriptor.channel_count as usize;
+    let planes = decode_planes::<P, E>(header, encoded, masks)?;
+
+    for (channel, plane) in planes.iter().enumerate() {
+        let pixels = decoded.chunks_exact_mut(E::PER_PIXEL).skip(channel);
+
+        for (pixel, elements) in pixels
+            .step_by(channel_count)
+            .zip(plane.chunks_exact(E::PER_PIXEL))
+        {
+            pixel.copy_from_slice(elements);
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes every channel into a plane of its own.
+///
+/// With the `parallel` feature, the channels are decoded on a thread each.
+/// The masked pixels keep the values the predictor saw.
+fn decode_planes<P: Pixel, E: Element<P>>(
+    header: &Header,
+    encoded: &[u8],
+    masks: &mut Masks,
+) -> Result<Vec<Vec<E>>, DecodeError> {
+    let channels = channel_data(header, encoded)
+        .zip(masks.iter_mut())
+        .enumerate();
+
+    #[cfg(feature = "parallel")]
+    let planes = thread::scope(|scope| {
+        let threads: Vec<_> = channels
+            .map(|(channel, (data, mask))| {
+                let mask = mask.take();
+                scope.spawn(move || decode_plane::<P, E>(header, channel, data, mask))
+            })
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap_or_else(|panic| resume_unwind(panic)))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+
+    #[cfg(not(feature = "parallel"))]
+    let planes = channels
+        .map(|(channel, (data, mask))| decode_plane::<P, E>(header, channel, data, mask.take()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(planes
+        .into_iter()
+        .zip(masks.iter_mut())
+        .map(|((plane, mask), channel_mask)| {
+            *channel_mask = mask;
+            plane
+        })
+        .collect())
+}
+
+/// Decodes a channel into a plane of its own and returns the plane and the mask of the channel.
+fn decode_plane<P: Pixel, E: Element<P>>(
+    header: &Header,
+    channel: usize,
+    data: &[u8],
+    mask: Option<ChannelMask>,
+) -> Result<(Vec<E>, Option<ChannelMask>), DecodeError> {
+    let descriptor = &header.descriptor;
+    let mut plane = try_vec(E::default(), descriptor.pixel_count() * E::PER_PIXEL)?;
+
+    #[cfg(feature = "parallel")]
+    if let Some(tiles) = &header.tiles {
+        verify_checksum(header, channel, data)?;
+        let threads = tile_threads(descriptor);
+        decode_tiles_parallel::<P, E>(
+            header,
+            tiles,
+            channel,
+            data,
+            &mut plane,
+            mask.as_ref(),
+            threads,
+        )?;
+
+        return Ok((plane, mask));
+    }
+
+    let mut decoded = Decoded::<P, _>::new(
+        descriptor.width as usize,
+        descriptor.height as usize,
+        Interleaved {
+            data: &mut plane,
+            channel_count: 1,
+            channel: 0,
+        },
+    );
+
+    decoded.mask = mask;
+    decode_channel(header, channel, data, &mut decoded)?;
+    let mask = decoded.mask.take();
+
+    Ok((plane, mask))
+}
+
+/// Decodes the channels one after another into the target.
+///
+/// The target is switched to the next channel by `select_channel` before the channel is decoded.
+/// The masks of the channels are lent to the decoder while the channel is decoded.
+fn decode_channels<P: Pixel, T: Target<P>>(
+    header: &Header,
+    encoded: &[u8],
+    decoded: &mut Decoded<P, T>,
+    masks: &mut Masks,
+    mut select_channel: impl FnMut(&mut T, usize),
+) -> Result<(), DecodeError> {
+    for (channel, data) in channel_data(header, encoded).enumerate() {
+        select_channel(&mut decoded.target, channel);
+        decoded.mask = masks[channel].take();
+
+        decode_channel(header, channel, data, decoded)?;
+
+        masks[channel] = decoded.mask.take();
+        decoded.next_channel();
+    }
+
+    Ok(())
+}
+
+/// Splits the encoded data into the data of the channels.
+fn channel_data<'a>(
+    header: &'a Header,
+    mut encoded: &'a [u8],
+) -> impl Iterator<Item = &'a [u8]> + 'a {
+    let channel_count = header.descriptor.channel_count as usize;
+
+    header.channel_sizes[..channel_count]
+        .iter()
+        .map(move |&channel_size| {
+            let (data, rest) = encoded.split_at(channel_size);
+            encoded = rest;
+            data
+        })
+}
+
+/// Verifies the checksum of a channel and decodes it into the target.
+fn decode_channel<P: Pixel, T: Target<P>>(
+    header: &Header,
+    channel: usize,
+    data: &[u8],
+    decoded: &mut Decoded<P, T>,
+) -> Result<(), DecodeError> {
+    let descriptor = &header.descriptor;
+
+    verify_checksum(header, channel, data)?;
+
+    if let Some(tiles) = &header.tiles {
+        let rows = 0..tiles.tiling.rows;
+        let mask = decoded.mask.as_ref();
+        decode_tiles(
+            header,
+            tiles,
+            channel,
+            data,
+            &mut decoded.target,
+            mask,
+            rows,
+        )?;
+    } else if data.len() < descriptor.channel_size() {
+        let mut encoded = Encoded::new(data);
+        encoded.channel = channel;
+
+        decoded.predictors = match header.coding.predictors {
+            true => Some(encoded.predictors(descriptor.height as usize)?),
+            false => None,
+        };
+
+        match header.coding.entropy {
+            true => {
+                let mut residuals = ResidualDecoder::new(encoded.index);
+                decode_residuals(
+                    &mut encoded,
+                    &mut residuals,
+                    decoded,
+                    descriptor.pixel_count(),
+                )?
+            }
+            false => decode(&mut encoded, decoded, descriptor.pixel_count())?,
+        }
+
+        if !decoded.is_empty() || !encoded.is_empty() {
+            return Err(encoded.corrupt(encoded.index));
+        }
+    } else {
+        data.chunks_exact(P::SIZE)
+            .for_each(|encoded| decoded.set_raw(P::read(encoded)));
+    }
+
+    Ok(())
 }
 
-/// Errors that may occur during DTM image decoding.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub enum DecodeError {
-    /// The input buffer does not contain enough encoded data.
-    InsufficientInputData,
-    /// The encoded header contains an invalid magic value.
-    ///
-    /// First four encoded must contain `b"dtm"`.
-    /// This usually indicates that the buffer does not contain a DTM image.
-    InvalidMagic,
-    /// The encoded header contains an invalid channels value.
-    ///
-    /// DTM supports 1 to 4 channels.
-    /// Any other value can not be produced by a valid encoder.
-    InvalidChannels,
-    /// An IO error occurred while loading the image.
-    IoError,
+/// Returns an error if the image stores checksums and the one of the channel does not match.
+fn verify_checksum(header: &Header, channel: usize, data: &[u8]) -> Result<(), DecodeError> {
+    if let Some(checksums) = header.checksums {
+        if Crc32::checksum(data) != checksums[channel] {
+            return Err(DecodeError::ChecksumMismatch { channel });
+        }
+    }
+
+    Ok(())
 }
 
-impl Error for DecodeError {}
+/// Decodes the given rows of tiles of a channel one after another and passes their pixels to the target.
+///
+/// The target receives the pixels relative to the first pixel of the first row of tiles.
+fn decode_tiles<P: Pixel, T: Target<P>>(
+    header: &Header,
+    tiles: &Tiles,
+    channel: usize,
+    encoded: &[u8],
+    target: &mut T,
+    mask: Option<&ChannelMask>,
+    rows: Range<usize>,
+) -> Result<(), DecodeError> {
+    let width = header.descriptor.width as usize;
+    let tiling = &tiles.tiling;
+    let start = rows.start * tiling.tile_height * width;
 
-impl Display for DecodeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            DecodeError::InsufficientInputData => {
-                f.write_str("The input buffer does not contain enough encoded data.")
-            }
-            DecodeError::InvalidMagic => {
-                f.write_str("The encoded header contains an invalid magic value.")
-            }
-            DecodeError::InvalidChannels => {
-                f.write_str("The encoded header contains an invalid channels value. DTM supports 1 to 4 channels")
+    let mut tile_data = try_vec(0, tiling.largest_tile().pixel_count() * P::SIZE)?;
+
+    for tile in rows.start * tiling.columns..rows.end * tiling.columns {
+        let rect = tiling.rect(tile);
+        let range = tiles.range(channel, tile);
+        let tile_data = &mut tile_data[..rect.pixel_count() * P::SIZE];
+
+        let mut encoded = Encoded::new(&encoded[range.clone()]);
+        encoded.channel = channel;
+        encoded.offset = range.start;
+
+        let mask = mask.map(|mask| mask.crop(&rect, width));
+        let mask = decode_tile::<P>(encoded, &rect, mask, header.coding, tile_data)?;
+
+        for (index, pixel) in tile_data.chunks_exact(P::SIZE).enumerate() {
+            let image_index = rect.image_index(index, width) - start;
+
+            match &mask {
+                Some(mask) if !mask.valid[index] => target.set_masked(image_index, P::read(pixel)),
+                _ => target.set(image_index, P::read(pixel)),
             }
-            DecodeError::IoError => { f.write_str("There occurred an io error while loading the image.")}
         }
     }
+
+    Ok(())
 }
 
-impl DTM {
-    /// Reads header from encoded DTM image.
-    /// The returned header can be analyzed before proceeding parsing with [`DTM::decode_skip_header`].
-    fn decode_header(encoded: &[u8]) -> Result<Header, DecodeError> {
-        let header = if let Some(header) = encoded.get(..DTM_HEADER_SIZE) {
-            header
-        } else {
-            return Err(DecodeError::InsufficientInputData);
-        };
+/// Returns the number of threads decoding the tiles of each channel,
+/// which share the cores with the other channels decoded at the same time.
+#[cfg(feature = "parallel")]
+fn tile_threads(descriptor: &DTM) -> usize {
+    let cores = thread::available_parallelism().map_or(1, usize::from);
+    (cores / descriptor.channel_count as usize).max(1)
+}
 
-        if &header[0..3] != DTM_MAGIC {
-            return Err(DecodeError::InvalidMagic);
-        }
+/// Decodes the tiles of a channel on up to `threads` threads into `plane`, which holds the channel alone.
+///
+/// The rows of tiles are split into a band per thread, each of which covers whole rows of the plane,
+/// so that the tiles or row groups of a single large channel are decoded in parallel.
+#[cfg(feature = "parallel")]
+fn decode_tiles_parallel<P: Pixel, E: Element<P>>(
+    header: &Header,
+    tiles: &Tiles,
+    channel: usize,
+    encoded: &[u8],
+    plane: &mut [E],
+    mask: Option<&ChannelMask>,
+    threads: usize,
+) -> Result<(), DecodeError> {
+    let descriptor = &header.descriptor;
+    let tiling = &tiles.tiling;
 
-        let pixel_size = header[3] as u32;
-        let width = u32::from_be_bytes(header[4..8].try_into().unwrap());
-        let height = u32::from_be_bytes(header[8..12].try_into().unwrap());
+    let threads = threads.clamp(1, tiling.rows.max(1));
+    let rows_per_thread = tiling.rows.div_ceil(threads);
+    let band_size = rows_per_thread * tiling.tile_height * descriptor.width as usize;
 
-        let mut channel_count = 0;
-        let mut channel_sizes = [0; 4];
-        let mut total_size = DTM_HEADER_SIZE;
+    thread::scope(|scope| {
+        let threads: Vec<_> = plane
+            .chunks_mut((band_size * E::PER_PIXEL).max(1))
+            .enumerate()
+            .map(|(band, data)| {
+                let rows = band * rows_per_thread..((band + 1) * rows_per_thread).min(tiling.rows);
 
-        for i in 0..4 {
-            let channel_size =
-                u32::from_be_bytes(header[12 + i * 4..16 + i * 4].try_into().unwrap()) as usize;
+                scope.spawn(move || {
+                    let mut target = Interleaved {
+                        data,
+                        channel_count: 1,
+                        channel: 0,
+                    };
 
-            if channel_size == 0 {
-                break;
-            }
+                    decode_tiles::<P, _>(header, tiles, channel, encoded, &mut target, mask, rows)
+                })
+            })
+            .collect();
 
-            channel_sizes[i] = channel_size;
-            total_size += channel_size;
-            channel_count += 1;
+        threads
+            .into_iter()
+            .try_for_each(|thread| thread.join().unwrap_or_else(|panic| resume_unwind(panic)))
+    })
+}
+
+/// Decodes a tile into `decoded`, which receives the pixels of the tile row by row.
+///
+/// The masked pixels of the tile are set to the nodata value of the channel.
+/// Returns the mask of the tile, which has to be cropped out of the mask of the channel.
+/// Compressed tiles of images with adaptive prediction start with the predictors of their rows.
+pub(crate) fn decode_tile<P: Pixel>(
+    mut encoded: Encoded,
+    rect: &Rect,
+    mask: Option<ChannelMask>,
+    coding: Coding,
+    decoded: &mut [u8],
+) -> Result<Option<ChannelMask>, DecodeError> {
+    let mut decoded = Decoded::<P, _>::new(
+        rect.width,
+        rect.height,
+        Interleaved {
+            data: decoded,
+            channel_count: 1,
+            channel: 0,
+        },
+    );
+
+    decoded.mask = mask;
+
+    // Tiles, which do not compress, are stored uncompressed like channels.
+    if encoded.data.len() == rect.pixel_count() * P::SIZE {
+        encoded
+            .data
+            .chunks_exact(P::SIZE)
+            .for_each(|encoded| decoded.set_raw(P::read(encoded)));
+    } else {
+        if coding.predictors {
+            decoded.predictors = Some(encoded.predictors(rect.height)?);
         }
 
-        Ok(Header {
-            descriptor: DTM {
-                pixel_size,
-                channel_count,
-                width,
-                height,
-            },
-            channel_sizes,
-            total_size,
-        })
-    }
+        match coding.entropy {
+            true => {
+                let mut residuals = ResidualDecoder::new(encoded.index);
+                decode_residuals(
+                    &mut encoded,
+                    &mut residuals,
+                    &mut decoded,
+                    rect.pixel_count(),
+                )?
+            }
+            false => decode(&mut encoded, &mut decoded, rect.pixel_count())?,
+        }
 
-    /// Decodes a DTM image from a file into a newly allocated `Vec`.
-    #[inline]
-    pub fn decode_file<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<u8>), DecodeError> {
-        let encoded = match fs::read(path) {
-            Ok(encoded) => encoded,
-            Err(_) => return Err(DecodeError::IoError),
-        };
+        if !decoded.is_empty() || !encoded.is_empty() {
+            return Err(encoded.corrupt(encoded.index));
+        }
+    }
 
-        DTM::decode_alloc(&encoded)
+    if let Some(mask) = &decoded.mask {
+        mask.fill(decoded.target.data.chunks_exact_mut(P::SIZE), 0);
     }
 
-    /// Decodes a DTM image from a byte slice into the `decoded` slice.
-    #[inline]
-    pub fn decode(encoded: &[u8], decoded: &mut [u8]) -> Result<Self, DecodeError> {
-        let Header {
-            descriptor,
-            channel_sizes,
-            total_size,
-        } = Self::decode_header(encoded)?;
+    Ok(decoded.mask)
+}
 
-        let mut encoded = match encoded.get(DTM_HEADER_SIZE..total_size) {
-            Some(encoded) => Encoded::new(encoded),
-            None => return Err(DecodeError::InsufficientInputData),
-        };
+/// Decodes the Rice coded residuals of a channel until the pixel at index `end` is reached.
+///
+/// The position of `residuals` is relative to the start of the encoded data,
+/// which is consumed up to the last byte read.
+/// Once the whole channel is decoded, the last run has to be complete.
+pub(crate) fn decode_residuals<P: Pixel, T: Target<P>>(
+    encoded: &mut Encoded,
+    residuals: &mut ResidualDecoder,
+    decoded: &mut Decoded<P, T>,
+    end: usize,
+) -> Result<(), DecodeError> {
+    let data = &encoded.data[..encoded.channel_size];
 
-        let mut decoded = Decoded::new(
-            descriptor.width as usize,
-            descriptor.height as usize,
-            descriptor.channel_count as usize,
-            decoded,
-        );
-
-        for &channel_size in &channel_sizes[0..descriptor.channel_count as usize] {
-            encoded.next_channel(channel_size);
-
-            if channel_size < descriptor.channel_size() {
-                decode(&mut encoded, &mut decoded)?;
-            } else if channel_size == descriptor.channel_size() {
-                encoded.data[..channel_size]
-                    .chunks_exact(2)
-                    .for_each(|encoded| {
-                        decoded.set(encoded[0] as u16 + ((encoded[1] as u16) << 8))
-                    });
-            } else {
-                return Err(DecodeError::InvalidChannels);
-            }
+    loop {
+        decoded.skip_masked(end);
 
-            decoded.next_channel();
+        if decoded.index >= end {
+            break;
         }
 
-        Ok(descriptor)
+        let residual = match residuals.next(data) {
+            Some(residual) => residual,
+            None => return Err(encoded.corrupt(residuals.bytes_read())),
+        };
+
+        let pixel = decoded.predict().add_diff(residual);
+        decoded.set(pixel);
     }
 
-    /// Decodes a DTM image from a byte slice into a newly allocated `Vec`.
-    #[inline]
-    pub fn decode_alloc(encoded: &[u8]) -> Result<(Self, Vec<u8>), DecodeError> {
-        let header = Self::decode_header(encoded)?;
-        let mut decoded = vec![0; header.descriptor.image_size()];
-        let descriptor = Self::decode(encoded, &mut decoded)?;
+    encoded.index = residuals.bytes_read();
 
-        Ok((descriptor, decoded))
+    if decoded.is_empty() && !residuals.is_finished() {
+        return Err(encoded.corrupt(encoded.index));
     }
+
+    Ok(())
 }
 
-fn decode(encoded: &mut Encoded, decoded: &mut Decoded) -> Result<(), DecodeError> {
-    while !encoded.is_empty() {
+/// Decodes the opcodes of a channel until either the encoded data is exhausted
+/// or the pixel at index `end` is reached.
+///
+/// Opcodes producing multiple pixels may decode past `end`, but never past the end of the channel.
+pub(crate) fn decode<P: Pixel, T: Target<P>>(
+    encoded: &mut Encoded,
+    decoded: &mut Decoded<P, T>,
+    end: usize,
+) -> Result<(), DecodeError> {
+    loop {
+        decoded.skip_masked(end);
+
+        if encoded.is_empty() || decoded.index >= end {
+            break;
+        }
+
         let byte = encoded.next();
 
         match byte {
@@ -176,49 +1955,85 @@ fn decode(encoded: &mut Encoded, decoded: &mut Decoded) -> Result<(), DecodeErro
             }
             SINGLE_DIFF..=SINGLE_DIFF_END => {
                 let diff = (MASK_6BIT & byte) as i32 - SINGLE_DIFF_RANGE;
-                let pixel = (decoded.paeth() as i32 + diff) as u16;
+                let pixel = decoded.predict().add_diff(diff);
                 decoded.set(pixel);
             }
             DOUBLE_DIFF..=DOUBLE_DIFF_END => {
+                if !decoded.available(2) {
+                    return Err(encoded.corrupt(encoded.index - 1));
+                }
+
                 let diff = (MASK_3BIT & (byte >> 3)) as i32 - DOUBLE_DIFF_RANGE;
-                let pixel = (decoded.paeth() as i32 + diff) as u16;
+                let pixel = decoded.predict().add_diff(diff);
                 decoded.set(pixel);
 
                 let diff = (MASK_3BIT & byte) as i32 - DOUBLE_DIFF_RANGE;
-                let pixel = (decoded.paeth() as i32 + diff) as u16;
+                let pixel = decoded.predict().add_diff(diff);
+                decoded.set(pixel);
+            }
+            WIDE_DIFF if P::WIDE_DIFF => {
+                if encoded.data.len() - encoded.index < 2 {
+                    return Err(encoded.corrupt(encoded.index - 1));
+                }
+
+                let diff = i16::from_le_bytes([encoded.next(), encoded.next()]) as i32;
+                let pixel = decoded.predict().add_diff(diff);
                 decoded.set(pixel);
             }
             RUN_LENGTH..=RUN_LENGTH_END => {
-                let run_length = (MASK_6BIT & byte + 1) as usize;
+                let run_length = (MASK_6BIT & (byte + 1)) as usize;
+
+                if !decoded.available(run_length) {
+                    return Err(encoded.corrupt(encoded.index - 1));
+                }
+
                 let pixel = decoded.previous();
                 (0..run_length).for_each(|_| decoded.set(pixel));
             }
             DEFAULT => {
-                decoded.set(encoded.next() as u16 + ((encoded.next() as u16) << 8));
+                if encoded.data.len() - encoded.index < P::SIZE {
+                    return Err(encoded.corrupt(encoded.index - 1));
+                }
+
+                decoded.set(P::read(&encoded.data[encoded.index..]));
+                encoded.index += P::SIZE;
             }
         }
     }
 
-    if decoded.is_empty() {
-        Ok(())
-    } else {
-        Err(DecodeError::InsufficientInputData)
-    }
+    Ok(())
 }
 
-struct Encoded<'a> {
-    data: &'a [u8],
-    index: usize,
-    channel_size: usize,
+pub(crate) struct Encoded<'a> {
+    pub(crate) data: &'a [u8],
+    pub(crate) index: usize,
+    /// The number of bytes, from which new opcodes may be read.
+    ///
+    /// The payload of an opcode may extend past this limit, but never past the end of `data`.
+    pub(crate) channel_size: usize,
+    pub(crate) channel: usize,
+    /// The position of `data` relative to the start of the channel.
+    pub(crate) offset: usize,
 }
 
 impl<'a> Encoded<'a> {
     #[inline]
-    fn new(data: &'a [u8]) -> Self {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
         Self {
             data,
             index: 0,
-            channel_size: 0,
+            channel_size: data.len(),
+            channel: 0,
+            offset: 0,
+        }
+    }
+
+    /// Returns the error for malformed data at the `index` of the encoded data.
+    #[inline]
+    pub(crate) fn corrupt(&self, index: usize) -> DecodeError {
+        DecodeError::CorruptData {
+            channel: self.channel,
+            offset: self.offset + index,
         }
     }
 
@@ -230,99 +2045,553 @@ impl<'a> Encoded<'a> {
     }
 
     #[inline]
-    fn is_empty(&self) -> bool {
-        self.index == self.channel_size
+    pub(crate) fn is_empty(&self) -> bool {
+        self.index >= self.channel_size
+    }
+
+    /// Reads the predictors of the given number of rows, which precede the opcodes.
+    pub(crate) fn predictors(&mut self, rows: usize) -> Result<Vec<Predictor>, DecodeError> {
+        let table = match self.data.get(self.index..self.index + rows) {
+            Some(table) if self.index + rows <= self.channel_size => table,
+            _ => return Err(self.corrupt(self.channel_size)),
+        };
+
+        let predictors =
+            Predictor::decode_table(table).map_err(|row| self.corrupt(self.index + row))?;
+        self.index += rows;
+
+        Ok(predictors)
     }
+}
+
+/// The storage the pixels of a channel are decoded into.
+pub(crate) trait Target<P> {
+    fn get(&self, index: usize) -> P;
 
+    fn set(&mut self, index: usize, pixel: P);
+
+    /// Sets a masked pixel, which is only read by the predictor.
     #[inline]
-    fn next_channel(&mut self, channel_size: usize) {
-        self.data = &self.data[self.channel_size..];
-        self.channel_size = channel_size;
-        self.index = 0;
+    fn set_masked(&mut self, index: usize, pixel: P) {
+        self.set(index, pixel);
+    }
+}
+
+/// A channel of a pixel-interleaved image, whose pixels are stored as bytes or as typed elements.
+pub(crate) struct Interleaved<'a, E = u8> {
+    pub(crate) data: &'a mut [E],
+    pub(crate) channel_count: usize,
+    pub(crate) channel: usize,
+}
+
+impl<P: Pixel, E: Element<P>> Target<P> for Interleaved<'_, E> {
+    #[inline]
+    fn get(&self, index: usize) -> P {
+        E::get(self.data, index * self.channel_count + self.channel)
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, pixel: P) {
+        E::set(self.data, index * self.channel_count + self.channel, pixel);
     }
 }
 
-struct Decoded<'a> {
+/// A channel of a pixel-interleaved image, whose rows are `row_stride` bytes apart.
+struct Strided<'a> {
+    data: &'a mut [u8],
     width: usize,
-    height: usize,
+    row_stride: usize,
+    channel_count: usize,
+    channel: usize,
+}
+
+impl Strided<'_> {
+    #[inline]
+    fn position(&self, index: usize, pixel_size: usize) -> usize {
+        let (y, x) = (index / self.width, index % self.width);
+        y * self.row_stride + (x * self.channel_count + self.channel) * pixel_size
+    }
+}
+
+impl<P: Pixel> Target<P> for Strided<'_> {
+    #[inline]
+    fn get(&self, index: usize) -> P {
+        P::read(&self.data[self.position(index, P::SIZE)..])
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, pixel: P) {
+        let position = self.position(index, P::SIZE);
+        pixel.write(&mut self.data[position..]);
+    }
+}
+
+/// A channel of a pixel-interleaved image of physical values.
+///
+/// Only the last row of pixel values is kept for the prediction,
+/// so that the values are converted in the same pass as they are decoded.
+struct Physical<'a, P> {
+    row: RowRing<P>,
+    data: &'a mut [f32],
     channel_count: usize,
-    data: &'a mut [u8],
-    cache: [u16; 64],
     channel: usize,
-    index: usize,
+    scale: f64,
+    offset: f64,
+}
+
+impl<P: Pixel> Target<P> for Physical<'_, P> {
+    #[inline]
+    fn get(&self, index: usize) -> P {
+        self.row.get(index)
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, pixel: P) {
+        self.row.set(index, pixel);
+        self.data[index * self.channel_count + self.channel] =
+            (pixel.value() * self.scale + self.offset) as f32;
+    }
+
+    #[inline]
+    fn set_masked(&mut self, index: usize, pixel: P) {
+        self.row.set(index, pixel);
+        self.data[index * self.channel_count + self.channel] = f32::NAN;
+    }
+}
+
+pub(crate) struct Decoded<P, T> {
+    width: usize,
+    height: usize,
+    pub(crate) target: T,
+    cache: [P; 64],
+    pub(crate) index: usize,
+    /// The validity mask of the current channel, if it is masked.
+    pub(crate) mask: Option<ChannelMask>,
+    /// The predictor of every row, if the channel is predicted adaptively.
+    pub(crate) predictors: Option<Vec<Predictor>>,
 }
 
-impl<'a> Decoded<'a> {
+impl<P: Pixel, T: Target<P>> Decoded<P, T> {
     #[inline]
-    pub fn new(width: usize, height: usize, channel_count: usize, data: &'a mut [u8]) -> Self {
+    pub(crate) fn new(width: usize, height: usize, target: T) -> Self {
         Self {
             width,
             height,
-            channel_count,
-            data,
-            cache: [0; 64],
-            channel: 0,
+            target,
+            cache: [P::default(); 64],
             index: 0,
+            mask: None,
+            predictors: None,
         }
     }
 
     #[inline]
-    fn get(&self, index: usize) -> u16 {
-        let index = (index * self.channel_count + self.channel) << 1;
-        u16::from_le_bytes(self.data[index..index + 2].try_into().unwrap())
+    fn get(&self, index: usize) -> P {
+        self.target.get(index)
     }
 
     #[inline]
-    fn previous(&self) -> u16 {
+    fn previous(&self) -> P {
         if self.index == 0 {
-            0
+            P::default()
         } else {
             self.get(self.index - 1)
         }
     }
 
+    /// Predicts the current pixel with the predictor of its row
+    /// or with the paeth filter if the channel is not predicted adaptively.
+    #[inline]
+    fn predict(&self) -> P {
+        match &self.predictors {
+            Some(predictors) => {
+                predictor::predict(predictors, self.index, self.width, |index| self.get(index))
+            }
+            None => self.paeth(),
+        }
+    }
+
     #[inline]
-    fn paeth(&mut self) -> u16 {
-        if self.index / self.width == 0 || self.index % self.width == 0 {
+    fn paeth(&self) -> P {
+        if self.index / self.width == 0 || self.index.is_multiple_of(self.width) {
             self.previous()
         } else {
             let previous = self.get(self.index - 1);
             let above = self.get(self.index - self.width);
             let diagonal = self.get(self.index - self.width - 1);
 
-            let p = previous.wrapping_add(above).wrapping_sub(diagonal);
+            P::paeth(previous, above, diagonal)
+        }
+    }
 
-            let diff_p_previous = p.abs_diff(previous);
-            let diff_p_above = p.abs_diff(above);
-            let diff_p_diagonal = p.abs_diff(diagonal);
+    #[inline]
+    pub(crate) fn set(&mut self, pixel: P) {
+        self.target.set(self.index, pixel);
+        self.cache[pixel.cache_index()] = pixel;
+        self.index += 1;
+    }
 
-            if diff_p_previous <= diff_p_above && diff_p_previous <= diff_p_diagonal {
-                previous
-            } else if diff_p_above <= diff_p_diagonal {
-                above
-            } else {
-                diagonal
+    /// Sets a pixel of a channel, which is stored uncompressed.
+    #[inline]
+    fn set_raw(&mut self, pixel: P) {
+        match &self.mask {
+            Some(mask) if !mask.valid[self.index] => {
+                self.target.set_masked(self.index, pixel);
+                self.index += 1;
             }
+            _ => self.set(pixel),
         }
     }
 
+    /// Skips the masked pixels before the pixel at index `end`,
+    /// which are set to their predecessor like in the encoder.
     #[inline]
-    fn set(&mut self, pixel: u16) {
-        let index = (self.index * self.channel_count + self.channel) << 1;
-        self.data[index..index + 2].copy_from_slice(&pixel.to_le_bytes());
-        self.cache[pixel as usize % 64] = pixel;
-        self.index += 1;
+    pub(crate) fn skip_masked(&mut self, end: usize) {
+        if let Some(mask) = &self.mask {
+            while self.index < end && !mask.valid[self.index] {
+                let pixel = self.previous();
+                self.target.set_masked(self.index, pixel);
+                self.index += 1;
+            }
+        }
     }
 
     #[inline]
-    fn is_empty(&self) -> bool {
+    pub(crate) fn is_empty(&self) -> bool {
         self.index == self.width * self.height
     }
 
+    /// Returns whether the next `count` pixels are left and unmasked,
+    /// so that they can be decoded by a single opcode.
+    #[inline]
+    fn available(&self, count: usize) -> bool {
+        match &self.mask {
+            Some(mask) => mask
+                .valid
+                .get(self.index..self.index + count)
+                .is_some_and(|valid| valid.iter().all(|&valid| valid)),
+            None => self.width * self.height - self.index >= count,
+        }
+    }
+
     #[inline]
-    fn next_channel(&mut self) {
-        self.cache = [0; 64];
-        self.channel += 1;
+    pub(crate) fn next_channel(&mut self) {
+        self.cache = [P::default(); 64];
         self.index = 0;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        metadata::Scale,
+        reader::DtmReader,
+        testing::{descriptor, roundtrip, terrain},
+    };
+
+    #[test]
+    fn rejects_sample_formats_in_legacy_headers() {
+        let encoded = include_bytes!("../tests/data/legacy.dtm");
+
+        // Version 0.1 never wrote a sample format next to the pixel size.
+        for sample_format in [0x10, 0x20] {
+            let mut encoded = encoded.to_vec();
+            encoded[3] |= sample_format;
+
+            assert!(matches!(
+                DTM::decode_header(&encoded),
+                Err(DecodeError::InvalidPixelSize(size)) if size == 2 | sample_format as u32
+            ));
+        }
+    }
+
+    #[test]
+    fn roundtrips_pixel_types() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (4, SampleFormat::Unsigned),
+            (1, SampleFormat::Signed),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in 1..=4 {
+                let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                roundtrip(&Encoder::new(descriptor), &terrain(&descriptor));
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrips_8_bit_images() {
+        // A classification raster, whose classes are close to each other modulo 256.
+        let classes_descriptor = descriptor(1, SampleFormat::Unsigned, 1, 37, 29);
+        let classes: Vec<u8> = (0..37 * 29)
+            .map(|index| [0, 255, 3, 254][(index % 37 / 8 + index / 37 / 6) % 4])
+            .collect();
+        let encoded = roundtrip(&Encoder::new(classes_descriptor), &classes);
+
+        // The diffs wrap around, so the class boundaries never need an uncompressed pixel.
+        assert!(!encoded[DTM_HEADER_SIZE..].contains(&DEFAULT));
+
+        // Every value of both sample formats round trips, also from a prediction far away.
+        for sample_format in [SampleFormat::Unsigned, SampleFormat::Signed] {
+            let descriptor = descriptor(1, sample_format, 2, 32, 16);
+            let decoded: Vec<u8> = (0..32 * 16 * 2)
+                .map(|index: usize| (index * 97) as u8)
+                .collect();
+
+            roundtrip(&Encoder::new(descriptor), &decoded);
+            roundtrip(
+                &Encoder::new(descriptor).adaptive_prediction(true),
+                &decoded,
+            );
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn decodes_tiles_on_several_threads() {
+        use crate::testing::assert_decodes;
+
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 37, 29);
+        let decoded = terrain(&descriptor);
+
+        for encoder in [
+            Encoder::new(descriptor).row_groups(4),
+            Encoder::new(descriptor).row_groups(1).nodata(0, 500),
+            Encoder::new(descriptor)
+                .tiles(16, 8)
+                .entropy_coding(true)
+                .adaptive_prediction(true)
+                .checksums(true),
+        ] {
+            let encoded = encoder.encode_alloc(&decoded).unwrap();
+            let (_, expected) = DTM::decode_alloc(&encoded).unwrap();
+
+            // The reader decodes the channels serially, which the other entry points are compared to.
+            assert_decodes(&encoded, &expected);
+
+            let header = DTM::decode_header(&encoded).unwrap();
+            let tiles = header.tiles.as_ref().unwrap();
+            let masks =
+                decode_masks(Chunks::new(&encoded[header.chunks.clone()]), &descriptor).unwrap();
+            let channels: Vec<_> = channel_data(&header, &encoded[header.header_size..]).collect();
+
+            for threads in 1..=9 {
+                for (channel, data) in channels.iter().enumerate() {
+                    let mut plane = vec![0; descriptor.channel_size()];
+                    let mask = masks[channel].as_ref();
+                    decode_tiles_parallel::<u16, u8>(
+                        &header, tiles, channel, data, &mut plane, mask, threads,
+                    )
+                    .unwrap();
+
+                    if let Some(mask) = mask {
+                        fill_channel::<u16, u8>(&mut plane, mask, 0, 1, 0);
+                    }
+
+                    let expected: Vec<u8> = expected
+                        .chunks_exact(2)
+                        .skip(channel)
+                        .step_by(2)
+                        .flatten()
+                        .copied()
+                        .collect();
+                    assert_eq!(plane, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_features() {
+        let mut encoded = include_bytes!("../tests/data/plain.dtm").to_vec();
+
+        encoded[4] = DTM_VERSION + 1;
+        assert!(matches!(
+            DTM::decode_alloc(&encoded),
+            Err(DecodeError::UnsupportedVersion(version)) if version == DTM_VERSION + 1
+        ));
+
+        encoded[4] = DTM_VERSION;
+        encoded[8] = 0x80;
+        assert!(matches!(
+            DTM::decode_alloc(&encoded),
+            Err(DecodeError::UnsupportedFeatures(_))
+        ));
+    }
+
+    /// Returns an image, whose single channel holds the given opcodes.
+    fn with_opcodes(descriptor: DTM, opcodes: &[u8]) -> Vec<u8> {
+        let mut encoded = Encoder::new(descriptor)
+            .encode_alloc(&vec![0; descriptor.image_size()])
+            .unwrap();
+
+        encoded.truncate(DTM_HEADER_SIZE);
+        encoded[20..24].copy_from_slice(&(opcodes.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(opcodes);
+        encoded
+    }
+
+    #[test]
+    fn rejects_truncated_opcodes() {
+        let samples = descriptor(2, SampleFormat::Unsigned, 1, 4, 2);
+        let wide = descriptor(4, SampleFormat::Unsigned, 1, 4, 2);
+
+        for (descriptor, opcodes, offset) in [
+            // A DEFAULT opcode missing a byte of its pixel.
+            (samples, &[DEFAULT, 0x12][..], 0),
+            (samples, &[DEFAULT, 0x12, 0x34, DEFAULT][..], 3),
+            // A run past the last pixel of the channel.
+            (samples, &[DEFAULT, 0x12, 0x34, RUN_LENGTH | 7][..], 3),
+            (samples, &[RUN_LENGTH | 4, RUN_LENGTH | 3][..], 1),
+            // A wide diff missing a byte of its difference.
+            (wide, &[DEFAULT, 1, 2, 3, 4, WIDE_DIFF, 0x12][..], 5),
+        ] {
+            let encoded = with_opcodes(descriptor, opcodes);
+            let corrupt = |result: Result<_, DecodeError>| {
+                matches!(
+                    result,
+                    Err(DecodeError::CorruptData { channel: 0, offset: at }) if at == offset
+                )
+            };
+
+            assert!(
+                corrupt(DTM::decode_alloc(&encoded).map(|_| ())),
+                "{opcodes:?}"
+            );
+            assert!(corrupt(DTM::verify(&encoded).map(|_| ())), "{opcodes:?}");
+
+            let mut reader = DtmReader::new(encoded.as_slice()).unwrap();
+            assert!(corrupt(reader.read_rows(|_| {})), "{opcodes:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_padded_textures() {
+        for (channel_count, layout) in [
+            (1, TexelLayout::Channels),
+            (1, TexelLayout::Rg),
+            (2, TexelLayout::Rg),
+            (3, TexelLayout::Rgba),
+            (2, TexelLayout::Rgba),
+        ] {
+            let descriptor = descriptor(2, SampleFormat::Unsigned, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let encoded = Encoder::new(descriptor)
+                .nodata(0, 500)
+                .encode_alloc(&decoded)
+                .unwrap();
+            let (_, expected) = DTM::decode_alloc(&encoded).unwrap();
+
+            let texel_size = 2 * layout.channel_count(channel_count) as usize;
+            let row_pitch = (37 * texel_size).next_multiple_of(256);
+            let mut texture = vec![0xAA; row_pitch * 29];
+
+            DTM::decode_texture(&encoded, &mut texture, row_pitch, layout).unwrap();
+
+            let pixel_size = 2 * channel_count as usize;
+
+            for (row, texels) in texture.chunks_exact(row_pitch).enumerate() {
+                let (texels, padding) = texels.split_at(37 * texel_size);
+                assert!(padding.iter().all(|&byte| byte == 0xAA));
+
+                for (x, texel) in texels.chunks_exact(texel_size).enumerate() {
+                    let start = (row * 37 + x) * pixel_size;
+                    assert_eq!(texel[..pixel_size], expected[start..start + pixel_size]);
+                    assert!(texel[pixel_size..].iter().all(|&byte| byte == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_textures() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 3, 37, 29);
+        let encoded = descriptor.encode_alloc(&terrain(&descriptor)).unwrap();
+        let mut texture = vec![0; 512 * 29];
+
+        assert!(matches!(
+            DTM::decode_texture(&encoded, &mut texture, 512, TexelLayout::Rg),
+            Err(DecodeError::InvalidTexelLayout)
+        ));
+        assert!(matches!(
+            DTM::decode_texture(&encoded, &mut texture, 37 * 8 - 1, TexelLayout::Rgba),
+            Err(DecodeError::InvalidStride)
+        ));
+        assert!(matches!(
+            DTM::decode_texture(
+                &encoded,
+                &mut texture[..28 * 512 + 37 * 8 - 1],
+                512,
+                TexelLayout::Rgba
+            ),
+            Err(DecodeError::InsufficientOutputBuffer)
+        ));
+    }
+
+    #[test]
+    fn decodes_physical_values() {
+        let descriptor = descriptor(2, SampleFormat::Signed, 2, 37, 29);
+        let decoded = terrain(&descriptor);
+        let valid: Vec<bool> = (0..descriptor.pixel_count())
+            .map(|index| index % 7 != 3)
+            .collect();
+        let scale = Scale {
+            scale: 0.1,
+            offset: -100.0,
+            unit: "m".to_string(),
+        };
+
+        // The first channel is scaled and masked by its nodata value,
+        // while the second one keeps its values and is masked explicitly.
+        let expected: Vec<f32> = decoded
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(index, pixel)| {
+                let value = i16::from_le_bytes([pixel[0], pixel[1]]) as f64;
+
+                match index % 2 {
+                    0 if value == 500.0 => f32::NAN,
+                    0 => (value * 0.1 - 100.0) as f32,
+                    _ if !valid[index / 2] => f32::NAN,
+                    _ => value as f32,
+                }
+            })
+            .collect();
+
+        assert!(expected.iter().any(|value| value.is_nan()));
+
+        for inter_channel in [false, true] {
+            for tiles in [None, Some((16, 8))] {
+                let mut encoder = Encoder::new(descriptor)
+                    .scale(0, scale.clone())
+                    .nodata(0, 500)
+                    .validity_mask(1, valid.clone())
+                    .inter_channel_prediction(inter_channel);
+
+                if let Some((tile_width, tile_height)) = tiles {
+                    encoder = encoder.tiles(tile_width, tile_height);
+                }
+
+                let encoded = encoder.encode_alloc(&decoded).unwrap();
+                let (_, values) = DTM::decode_f32_alloc(&encoded).unwrap();
+
+                assert_eq!(values.len(), expected.len());
+
+                for (value, expected) in values.iter().zip(&expected) {
+                    assert!(
+                        value.to_bits() == expected.to_bits()
+                            || value.is_nan() && expected.is_nan()
+                    );
+                }
+            }
+        }
+    }
+}
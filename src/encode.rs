@@ -1,14 +1,29 @@
 use crate::{
-    CACHE, DEFAULT, DOUBLE_DIFF, DOUBLE_DIFF_RANGE, DTM, DTM_HEADER_SIZE, DTM_MAGIC, RUN_LENGTH,
-    SINGLE_DIFF, SINGLE_DIFF_RANGE,
+    chunk::{encode_chunks, Chunk},
+    crc::Crc32,
+    mask::{ChannelMask, Masks},
+    metadata::{Georeference, Scale},
+    pixel::{with_pixel, Pixel},
+    predictor::{self, Predictor},
+    rice::ResidualEncoder,
+    tile::{Rect, Tiling},
+    Coding, SampleFormat, CACHE, DEFAULT, DOUBLE_DIFF, DOUBLE_DIFF_RANGE, DTM, DTM_HEADER_SIZE,
+    DTM_MAGIC, DTM_VERSION, FEATURE_CHECKSUMS, FEATURE_CHUNKS, FEATURE_ENTROPY,
+    FEATURE_INTER_CHANNEL, FEATURE_PREDICTORS, FEATURE_TILES, RUN_LENGTH, SINGLE_DIFF,
+    SINGLE_DIFF_RANGE, WIDE_DIFF, WIDE_DIFF_RANGE,
 };
 use std::{
     error::Error,
     fmt::{self, Display},
-    fs,
+    fs::File,
+    io::{self, BufWriter, Cursor, Seek, SeekFrom, Write},
+    marker::PhantomData,
     path::Path,
 };
 
+#[cfg(feature = "parallel")]
+use std::{panic::resume_unwind, thread};
+
 // static mut C_CACHE: i32 = 0;
 // static mut C_SINGLE_DIFF: i32 = 0;
 // static mut C_DOUBLE_DIFF: i32 = 0;
@@ -17,17 +32,48 @@ use std::{
 // static mut C_DEFAULT: i32 = 0;
 
 /// Errors that may occur during DTM image encoding.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Debug)]
 pub enum EncodeError {
     /// The input buffer does not contain enough pixel data.
     InsufficientInputData,
     /// The output buffer is too small to fit the encoded image.
     InsufficientOutputBuffer,
+    /// The descriptor contains an invalid pixel size value.
+    ///
+    /// DTM supports 8, 16 and 32 bit pixels.
+    InvalidPixelSize(u32),
+    /// The descriptor contains a sample format, which does not support the pixel size.
+    ///
+    /// Floating point pixels have to be 32 bit.
+    InvalidSampleFormat,
+    /// The descriptor does not describe the pixel type of a typed entry point.
+    ///
+    /// E.g. [`DTM::encode_i16`] requires a pixel size of 2 and signed samples.
+    SampleTypeMismatch,
+    /// The validity mask or the nodata value of the channel does not match the image.
+    ///
+    /// The mask has to contain a value for every pixel and the nodata value has to fit into a pixel.
+    InvalidMask { channel: usize },
+    /// The width or the height of the tiles is zero.
+    InvalidTileSize,
+    /// Both tiles and row groups were requested, but an image can only have one tiling.
+    ConflictingTiling,
+    /// The number of planes does not match the channel count of the descriptor.
+    PlaneCountMismatch,
+    /// The row stride is smaller than a row of the image.
+    InvalidStride,
     /// An IO error occurred while saving the image.
-    IoError,
+    IoError(io::Error),
 }
 
-impl Error for EncodeError {}
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncodeError::IoError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
 
 impl Display for EncodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -38,91 +84,1098 @@ impl Display for EncodeError {
             EncodeError::InsufficientOutputBuffer => {
                 f.write_str("Output buffer is too small to fit the encoded image")
             }
-            EncodeError::IoError => {
-                f.write_str("There occurred an io error while saving the image.")
+            EncodeError::InvalidPixelSize(pixel_size) => {
+                write!(f, "The descriptor contains an invalid pixel size value of {pixel_size}. DTM supports 8, 16 and 32 bit pixels")
+            }
+            EncodeError::InvalidSampleFormat => f.write_str(
+                "The descriptor contains an invalid sample format. Floating point pixels have to be 32 bit",
+            ),
+            EncodeError::SampleTypeMismatch => {
+                f.write_str("The descriptor does not describe the provided pixel type.")
+            }
+            EncodeError::InvalidMask { channel } => write!(
+                f,
+                "The validity mask or the nodata value of channel {channel} does not match the image"
+            ),
+            EncodeError::InvalidTileSize => {
+                f.write_str("The width or the height of the tiles is zero.")
+            }
+            EncodeError::ConflictingTiling => {
+                f.write_str("Tiles and row groups cannot be combined.")
+            }
+            EncodeError::PlaneCountMismatch => {
+                f.write_str("The number of planes does not match the channel count.")
+            }
+            EncodeError::InvalidStride => {
+                f.write_str("The row stride is smaller than a row of the image.")
+            }
+            EncodeError::IoError(error) => {
+                write!(
+                    f,
+                    "There occurred an io error while saving the image: {error}"
+                )
             }
         }
     }
 }
 
+impl From<io::Error> for EncodeError {
+    fn from(error: io::Error) -> Self {
+        EncodeError::IoError(error)
+    }
+}
+
 impl DTM {
     /// Encodes a DTM image from a pixel slice into a file.
+    ///
+    /// Use an [`Encoder`] to enable optional features of the format.
     #[inline]
     pub fn encode_file<P: AsRef<Path>>(&self, path: P, decoded: &[u8]) -> Result<(), EncodeError> {
-        let encoded = self.encode_alloc(decoded)?;
+        Encoder::new(*self).encode_file(path, decoded)
+    }
 
-        match fs::write(path, encoded) {
-            Ok(_) => Ok(()),
-            Err(_) => return Err(EncodeError::IoError),
+    /// Encodes a DTM image from a pixel slice into a newly allocated `Vec`.
+    #[inline]
+    pub fn encode_alloc(&self, decoded: &[u8]) -> Result<Vec<u8>, EncodeError> {
+        Encoder::new(*self).encode_alloc(decoded)
+    }
+
+    /// Encodes a DTM image of signed 16 bit pixels into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and signed samples.
+    #[inline]
+    pub fn encode_i16(&self, decoded: &[i16]) -> Result<Vec<u8>, EncodeError> {
+        Encoder::new(*self).encode_i16(decoded)
+    }
+
+    /// Encodes a DTM image, which is a window of a larger pixel slice, into a newly allocated `Vec`.
+    ///
+    /// See [`Encoder::encode_strided`].
+    #[inline]
+    pub fn encode_strided(
+        &self,
+        decoded: &[u8],
+        offset: usize,
+        row_stride: usize,
+    ) -> Result<Vec<u8>, EncodeError> {
+        Encoder::new(*self).encode_strided(decoded, offset, row_stride)
+    }
+
+    /// Encodes a DTM image of unsigned 16 bit pixels into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and unsigned samples.
+    #[inline]
+    pub fn encode_u16(&self, decoded: &[u16]) -> Result<Vec<u8>, EncodeError> {
+        Encoder::new(*self).encode_u16(decoded)
+    }
+
+    /// Encodes a DTM image of unsigned 16 bit pixels, which are stored as a plane per channel,
+    /// into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and unsigned samples.
+    #[inline]
+    pub fn encode_planar(&self, planes: &[&[u16]]) -> Result<Vec<u8>, EncodeError> {
+        Encoder::new(*self).encode_planar(planes)
+    }
+
+    /// Encodes a DTM image from a pixel slice into a writer and returns the number of bytes written.
+    #[inline]
+    pub fn encode_to_writer<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        decoded: &[u8],
+    ) -> Result<usize, EncodeError> {
+        Encoder::new(*self).encode_to_writer(writer, decoded)
+    }
+}
+
+/// An encoder for DTM images, which configures the optional features of the format.
+///
+/// ```no_run
+/// # use dtm::{chunk::Chunk, encode::Encoder, SampleFormat, DTM};
+/// let descriptor = DTM {
+///     pixel_size: 2,
+///     sample_format: SampleFormat::Unsigned,
+///     channel_count: 1,
+///     width: 16,
+///     height: 16,
+/// };
+///
+/// Encoder::new(descriptor)
+///     .checksums(true)
+///     .chunk(Chunk::text("source", "SRTM 1 arc-second"))
+///     .encode_file("image.dtm", &[0; 512])
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Encoder {
+    descriptor: DTM,
+    checksums: bool,
+    chunks: Vec<Chunk>,
+    georeference: Option<Georeference>,
+    scales: [Option<Scale>; 4],
+    masks: [Option<Vec<bool>>; 4],
+    nodata: [Option<u32>; 4],
+    tile_size: Option<(u32, u32)>,
+    row_groups: Option<u32>,
+    big_endian: bool,
+    adaptive_prediction: bool,
+    inter_channel_prediction: bool,
+    entropy_coding: bool,
+}
+
+impl Encoder {
+    /// Creates an encoder for images matching the descriptor, with all optional features disabled.
+    pub fn new(descriptor: DTM) -> Self {
+        Self {
+            descriptor,
+            checksums: false,
+            chunks: Vec::new(),
+            georeference: None,
+            scales: Default::default(),
+            masks: Default::default(),
+            nodata: Default::default(),
+            tile_size: None,
+            row_groups: None,
+            big_endian: false,
+            adaptive_prediction: false,
+            inter_channel_prediction: false,
+            entropy_coding: false,
         }
     }
 
+    /// Reads the pixels of the byte slices passed to the encoder as big-endian instead of little-endian.
+    ///
+    /// The pixels are converted to little-endian before encoding, so the encoded image is the same.
+    /// Typed pixel slices, e.g. in [`Encoder::encode_u16`], are not affected.
+    pub fn big_endian(mut self, big_endian: bool) -> Self {
+        self.big_endian = big_endian;
+        self
+    }
+
+    /// Stores a CRC-32 checksum of every encoded channel, which is verified during decoding.
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    /// Stores the placement of the image in a coordinate reference system.
+    pub fn georeference(mut self, georeference: Georeference) -> Self {
+        self.georeference = Some(georeference);
+        self
+    }
+
+    /// Stores the mapping of the pixel values of the channel to physical values.
+    ///
+    /// The scale is applied by [`DTM::decode_f32_alloc`](crate::DTM::decode_f32_alloc).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channel is not smaller than 4.
+    pub fn scale(mut self, channel: usize, scale: Scale) -> Self {
+        self.scales[channel] = Some(scale);
+        self
+    }
+
+    /// Masks the pixels of the channel, which hold the nodata value.
+    ///
+    /// The value is given as the bits of a pixel, e.g. `f32::to_bits` for floats
+    /// or `-9999i16 as u16 as u32` for signed 16 bit pixels.
+    /// Masked pixels are skipped by the encoder and decoded as the nodata value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channel is not smaller than 4.
+    pub fn nodata(mut self, channel: usize, value: u32) -> Self {
+        self.nodata[channel] = Some(value);
+        self
+    }
+
+    /// Masks the pixels of the channel, whose entry in `valid` is `false`.
+    ///
+    /// If a nodata value is set for the channel as well, the mask takes precedence,
+    /// but masked pixels are still decoded as the nodata value.
+    /// Otherwise they are decoded as zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the channel is not smaller than 4.
+    pub fn validity_mask(mut self, channel: usize, valid: Vec<bool>) -> Self {
+        self.masks[channel] = Some(valid);
+        self
+    }
+
+    /// Splits the channels into tiles of the given size, which are encoded independently.
+    ///
+    /// This allows [`DTM::decode_region`](crate::DTM::decode_region) to decode only the tiles
+    /// intersecting a region, at the cost of a slightly worse compression.
+    /// The tiles in the last column and row are cut off at the border of the image.
+    pub fn tiles(mut self, tile_width: u32, tile_height: u32) -> Self {
+        self.tile_size = Some((tile_width, tile_height));
+        self
+    }
+
+    /// Restarts the prediction every `rows` rows and stores the offset of each restart point.
+    ///
+    /// This allows [`DTM::decode_rows`](crate::DTM::decode_rows) to decode only the row groups
+    /// containing the requested rows.
+    /// Row groups are stored as tiles spanning the whole width of the image,
+    /// so encoding fails with [`EncodeError::ConflictingTiling`] if [`Encoder::tiles`] is set as well.
+    ///
+    /// With the `parallel` feature, the row groups of a channel are decoded on several threads
+    /// by [`DTM::decode`](crate::DTM::decode) and the typed entry points.
+    pub fn row_groups(mut self, rows: u32) -> Self {
+        self.row_groups = Some(rows);
+        self
+    }
+
+    /// Selects the predictor of each row from left, up, average, paeth and the median edge detector
+    /// of LOCO-I, instead of always using the paeth filter.
+    ///
+    /// The first pixel of each row is predicted from the pixel above.
+    /// This improves the compression of smooth terrain, but the image can only be read
+    /// by decoders supporting adaptive prediction.
+    pub fn adaptive_prediction(mut self, adaptive_prediction: bool) -> Self {
+        self.adaptive_prediction = adaptive_prediction;
+        self
+    }
+
+    /// Encodes every channel but the first as the differences to the preceding channel.
+    ///
+    /// This suits channels which are almost identical, e.g. the minimum and maximum of a pyramid,
+    /// whose second channel is then stored as `max - min`.
+    /// The differences wrap around, so 16 bit channels should be ordered such that they are positive.
+    /// [`DtmReader`](crate::reader::DtmReader) keeps the preceding channel in memory to restore them,
+    /// i.e. up to `width * height * pixel_size` bytes instead of a few rows,
+    /// so this is not suited to images that are streamed because they do not fit into memory.
+    pub fn inter_channel_prediction(mut self, inter_channel_prediction: bool) -> Self {
+        self.inter_channel_prediction = inter_channel_prediction;
+        self
+    }
+
+    /// Stores the residuals of the predictors as adaptive Rice codes instead of opcodes.
+    ///
+    /// This is a high compression mode for archival, which trades decoding speed for size.
+    /// On the synthetic terrain of `examples/entropy.rs`, it saves a fifth to two fifths of the size,
+    /// while decoding takes two to three times as long, and more on other data.
+    /// Synthetic images with long runs of identical residuals may compress better with the opcodes.
+    /// [`DtmReader`](crate::reader::DtmReader) reads every channel at once in this mode.
+    pub fn entropy_coding(mut self, entropy_coding: bool) -> Self {
+        self.entropy_coding = entropy_coding;
+        self
+    }
+
+    fn coding(&self) -> Coding {
+        Coding {
+            predictors: self.adaptive_prediction,
+            entropy: self.entropy_coding,
+        }
+    }
+
+    /// Appends a metadata chunk to the chunk section of the header.
+    ///
+    /// The chunks are stored in the order they are added.
+    pub fn chunk(mut self, chunk: Chunk) -> Self {
+        self.chunks.push(chunk);
+        self
+    }
+
+    /// Encodes a DTM image from a pixel slice into a file.
+    #[inline]
+    pub fn encode_file<P: AsRef<Path>>(&self, path: P, decoded: &[u8]) -> Result<(), EncodeError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        self.encode_to_writer(&mut writer, decoded)?;
+        writer.flush()?;
+
+        Ok(())
+    }
+
     /// Encodes a DTM image from a pixel slice into a newly allocated `Vec`.
     #[inline]
     pub fn encode_alloc(&self, decoded: &[u8]) -> Result<Vec<u8>, EncodeError> {
-        let mut decoded = match decoded.get(..self.image_size()) {
-            Some(decoded) => Decoded::new(
-                self.width as usize,
-                self.height as usize,
-                self.channel_count as usize,
-                decoded,
-            ),
+        let mut writer = Cursor::new(Vec::new());
+
+        self.encode_to_writer(&mut writer, decoded)?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Encodes a DTM image, which is a window of a larger pixel slice, into a newly allocated `Vec`.
+    ///
+    /// The first row of the image starts at `offset` and the rows are `row_stride` bytes apart,
+    /// so that e.g. a tile of a mosaic can be encoded without copying it first.
+    pub fn encode_strided(
+        &self,
+        decoded: &[u8],
+        offset: usize,
+        row_stride: usize,
+    ) -> Result<Vec<u8>, EncodeError> {
+        let mut writer = Cursor::new(Vec::new());
+
+        self.encode_window(
+            &mut writer,
+            decoded.get(offset..).unwrap_or(&[]),
+            row_stride,
+        )?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Encodes a DTM image of unsigned 16 bit pixels into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and unsigned samples.
+    pub fn encode_u16(&self, decoded: &[u16]) -> Result<Vec<u8>, EncodeError> {
+        let decoded = self.samples(decoded, SampleFormat::Unsigned)?;
+        let mut writer = Cursor::new(Vec::new());
+
+        self.encode_layout(&mut writer, Layout::Samples(decoded))?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Encodes a DTM image of signed 16 bit pixels into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and signed samples.
+    pub fn encode_i16(&self, decoded: &[i16]) -> Result<Vec<u8>, EncodeError> {
+        let decoded = self.samples(decoded, SampleFormat::Signed)?;
+        let mut writer = Cursor::new(Vec::new());
+
+        self.encode_layout(&mut writer, Layout::Signed(decoded))?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Checks that the pixels are 16 bit integers of the given sample format
+    /// and returns the samples of the image.
+    fn samples<'a, T>(
+        &self,
+        decoded: &'a [T],
+        sample_format: SampleFormat,
+    ) -> Result<&'a [T], EncodeError> {
+        let descriptor = &self.descriptor;
+
+        if descriptor.pixel_size != 2 || descriptor.sample_format != sample_format {
+            return Err(EncodeError::SampleTypeMismatch);
+        }
+
+        decoded
+            .get(..descriptor.image_size() / 2)
+            .ok_or(EncodeError::InsufficientInputData)
+    }
+
+    /// Encodes a DTM image of unsigned 16 bit pixels, which are stored as a plane per channel,
+    /// into a newly allocated `Vec`.
+    ///
+    /// The descriptor has to have a pixel size of 2 and unsigned samples,
+    /// and there has to be a plane for every channel.
+    pub fn encode_planar(&self, planes: &[&[u16]]) -> Result<Vec<u8>, EncodeError> {
+        let descriptor = &self.descriptor;
+
+        if descriptor.pixel_size != 2 || descriptor.sample_format != SampleFormat::Unsigned {
+            return Err(EncodeError::SampleTypeMismatch);
+        }
+
+        if planes.len() != descriptor.channel_count as usize {
+            return Err(EncodeError::PlaneCountMismatch);
+        }
+
+        if planes
+            .iter()
+            .any(|plane| plane.len() < descriptor.pixel_count())
+        {
+            return Err(EncodeError::InsufficientInputData);
+        }
+
+        let mut writer = Cursor::new(Vec::new());
+
+        self.encode_layout(&mut writer, Layout::Planar(planes))?;
+
+        Ok(writer.into_inner())
+    }
+
+    /// Encodes a DTM image from a pixel slice into a writer and returns the number of bytes written.
+    ///
+    /// Each channel is written as soon as it is encoded, so only a single channel is buffered at a time.
+    /// The channel sizes in the header are patched by seeking back once all channels are written.
+    pub fn encode_to_writer<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        decoded: &[u8],
+    ) -> Result<usize, EncodeError> {
+        self.encode_window(writer, decoded, self.descriptor.row_size())
+    }
+
+    /// Encodes the image, whose rows are `row_stride` bytes apart in `decoded`, into a writer
+    /// and returns the number of bytes written.
+    fn encode_window<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        decoded: &[u8],
+        row_stride: usize,
+    ) -> Result<usize, EncodeError> {
+        self.validate()?;
+
+        let descriptor = &self.descriptor;
+        let row_size = descriptor.row_size();
+
+        if row_stride < row_size {
+            return Err(EncodeError::InvalidStride);
+        }
+
+        let decoded = match descriptor
+            .strided_size(row_size, row_stride)
+            .and_then(|size| decoded.get(..size))
+        {
+            Some(decoded) => decoded,
             None => return Err(EncodeError::InsufficientInputData),
         };
 
-        let mut data = vec![0; DTM_HEADER_SIZE + 3 * self.image_size() / 2];
-        let mut encoded = Encoded::new(&mut data[DTM_HEADER_SIZE..]);
-
-        let mut channel_sizes = [0; 4];
-        let mut total_size = DTM_HEADER_SIZE;
-
-        for channel_size in &mut channel_sizes[0..self.channel_count as usize] {
-            encode(&mut encoded, &mut decoded);
-
-            if encoded.index >= self.channel_size() {
-                encoded.index = 0;
-                decoded
-                    .data
-                    .chunks(2)
-                    .skip(decoded.channel)
-                    .step_by(decoded.channel_count)
-                    .for_each(|pixel| {
-                        encoded.data[encoded.index..encoded.index + 2].copy_from_slice(pixel);
-                        encoded.index += 2;
-                    });
+        let pixel_size = descriptor.pixel_size as usize;
+
+        if self.big_endian && pixel_size > 1 {
+            let decoded: Vec<u8> = decoded
+                .chunks(row_stride.max(1))
+                .flat_map(|row| row[..row_size].chunks_exact(pixel_size))
+                .flat_map(|pixel| pixel.iter().rev())
+                .copied()
+                .collect();
+
+            return self.encode_layout(writer, Layout::Interleaved(&decoded));
+        }
+
+        match row_stride == row_size {
+            true => self.encode_layout(writer, Layout::Interleaved(decoded)),
+            false => self.encode_layout(writer, Layout::Strided(decoded, row_stride)),
+        }
+    }
+
+    fn validate(&self) -> Result<(), EncodeError> {
+        let descriptor = &self.descriptor;
+
+        if !matches!(descriptor.pixel_size, 1 | 2 | 4) {
+            return Err(EncodeError::InvalidPixelSize(descriptor.pixel_size));
+        }
+
+        if !descriptor.sample_format.supports(descriptor.pixel_size) {
+            return Err(EncodeError::InvalidSampleFormat);
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the pixels, whose size has been validated, into a writer
+    /// and returns the number of bytes written.
+    fn encode_layout<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        decoded: Layout<'_>,
+    ) -> Result<usize, EncodeError> {
+        let descriptor = &self.descriptor;
+
+        if self.tile_size.is_some() && self.row_groups.is_some() {
+            return Err(EncodeError::ConflictingTiling);
+        }
+
+        let tiling = match self.tile_size() {
+            Some((0, _) | (_, 0)) => return Err(EncodeError::InvalidTileSize),
+            Some((tile_width, tile_height)) => {
+                Some(Tiling::new(descriptor, tile_width, tile_height))
+            }
+            None => None,
+        };
+
+        let masks = self.masks(decoded)?;
+        let mask_chunks: Vec<Chunk> = masks
+            .iter()
+            .enumerate()
+            .filter_map(|(channel, mask)| mask.as_ref().map(|mask| mask.encode(channel)))
+            .collect();
+
+        let mut channels = Channels {
+            tile_offsets: vec![
+                0;
+                tiling.as_ref().map_or(0, Tiling::tile_count)
+                    * descriptor.channel_count as usize
+            ],
+            ..Default::default()
+        };
+        let header_size = self.encode_header(&channels, &mask_chunks).len();
+
+        let start = writer.stream_position()?;
+        writer.write_all(&vec![0; header_size])?;
+
+        with_pixel!(descriptor, P => {
+            self.encode_channels::<P, W>(writer, decoded, &masks, tiling.as_ref(), &mut channels)?
+        });
+
+        let total_size = header_size + channels.sizes.iter().sum::<usize>();
+        let header = self.encode_header(&channels, &mask_chunks);
+
+        writer.seek(SeekFrom::Start(start))?;
+        writer.write_all(&header)?;
+        writer.seek(SeekFrom::Start(start + total_size as u64))?;
+
+        Ok(total_size)
+    }
+
+    /// Returns the masks of the channels, which are either given or derived from the nodata values.
+    fn masks(&self, decoded: Layout<'_>) -> Result<Masks, EncodeError> {
+        let descriptor = &self.descriptor;
+        let pixel_size = descriptor.pixel_size as usize;
+        let channel_count = descriptor.channel_count as usize;
+
+        let mut masks = Masks::default();
+
+        for (channel, mask) in masks.iter_mut().enumerate() {
+            let nodata = self.nodata[channel];
+
+            if self.masks[channel].is_none() && nodata.is_none() {
+                continue;
+            }
+
+            if channel >= channel_count
+                || nodata.is_some_and(|nodata| pixel_size < 4 && nodata >> (8 * pixel_size) != 0)
+            {
+                return Err(EncodeError::InvalidMask { channel });
+            }
+
+            let valid = match &self.masks[channel] {
+                Some(valid) if valid.len() == descriptor.pixel_count() => valid.clone(),
+                Some(_) => return Err(EncodeError::InvalidMask { channel }),
+                None => {
+                    let nodata = nodata.unwrap_or(0);
+                    let mut plane = Vec::new();
+                    let (data, stride, channel) = decoded.channel(descriptor, channel, &mut plane);
+
+                    (0..descriptor.pixel_count())
+                        .map(|index| data.value(index * stride + channel, pixel_size) != nodata)
+                        .collect()
+                }
             };
 
-            *channel_size = encoded.index;
-            total_size += *channel_size;
+            *mask = Some(ChannelMask { valid, nodata });
+        }
+
+        Ok(masks)
+    }
+
+    /// Encodes the channels one after another and writes them to the writer.
+    fn encode_channels<P: Pixel, W: Write>(
+        &self,
+        writer: &mut W,
+        decoded: Layout<'_>,
+        masks: &Masks,
+        tiling: Option<&Tiling>,
+        channels: &mut Channels,
+    ) -> Result<(), EncodeError> {
+        let descriptor = &self.descriptor;
+        let channel_count = descriptor.channel_count as usize;
+        let tile_count = tiling.map_or(0, Tiling::tile_count);
+
+        #[cfg(feature = "parallel")]
+        if channel_count > 1 {
+            return self.encode_channels_parallel::<P, W>(writer, decoded, masks, tiling, channels);
+        }
+
+        let mut data = vec![0; self.max_channel_size::<P>(tiling)];
+
+        for channel in 0..channel_count {
+            let offsets = &mut channels.tile_offsets[channel * tile_count..][..tile_count];
+            let size =
+                self.encode_channel_of::<P>(decoded, channel, masks, tiling, &mut data, offsets);
+
+            self.write_channel(writer, channel, &data[..size], channels)?;
+        }
+
+        Ok(())
+    }
+
+    /// Encodes the channels on a thread each and writes them to the writer in order.
+    ///
+    /// Every channel is encoded into a buffer of its own, so the output is identical
+    /// to the one of the serial encoder.
+    #[cfg(feature = "parallel")]
+    fn encode_channels_parallel<P: Pixel, W: Write>(
+        &self,
+        writer: &mut W,
+        decoded: Layout<'_>,
+        masks: &Masks,
+        tiling: Option<&Tiling>,
+        channels: &mut Channels,
+    ) -> Result<(), EncodeError> {
+        let descriptor = &self.descriptor;
+        let channel_count = descriptor.channel_count as usize;
+        let tile_count = tiling.map_or(0, Tiling::tile_count);
+
+        let encoded: Vec<_> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..channel_count)
+                .map(|channel| {
+                    scope.spawn(move || {
+                        let mut data = vec![0; self.max_channel_size::<P>(tiling)];
+                        let mut offsets = vec![0; tile_count];
+
+                        let size = self.encode_channel_of::<P>(
+                            decoded,
+                            channel,
+                            masks,
+                            tiling,
+                            &mut data,
+                            &mut offsets,
+                        );
+
+                        data.truncate(size);
+                        (data, offsets)
+                    })
+                })
+                .collect();
+
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap_or_else(|panic| resume_unwind(panic)))
+                .collect()
+        });
+
+        for (channel, (data, offsets)) in encoded.iter().enumerate() {
+            channels.tile_offsets[channel * tile_count..][..tile_count].copy_from_slice(offsets);
+            self.write_channel(writer, channel, data, channels)?;
+        }
+
+        Ok(())
+    }
+
+    /// Encodes a channel of the image into `encoded` and returns the size of the encoded channel.
+    ///
+    /// The offsets of the tiles are stored in `offsets`, if the image is tiled.
+    fn encode_channel_of<P: Pixel>(
+        &self,
+        decoded: Layout<'_>,
+        channel: usize,
+        masks: &Masks,
+        tiling: Option<&Tiling>,
+        encoded: &mut [u8],
+        offsets: &mut [usize],
+    ) -> usize {
+        let descriptor = &self.descriptor;
+        let coding = self.coding();
+        let (width, height) = (descriptor.width as usize, descriptor.height as usize);
+        let mask = masks[channel].as_ref();
+
+        let mut plane = Vec::new();
+
+        let (data, stride, channel) = match self.inter_channel_prediction && channel > 0 {
+            true => {
+                let reference_mask = masks[channel - 1].as_ref();
+                plane = channel_residuals::<P>(descriptor, decoded, channel, reference_mask);
+                (Pixels::Bytes(&plane), 1, 0)
+            }
+            false => decoded.channel(descriptor, channel, &mut plane),
+        };
+
+        let mut decoded = Decoded::<P>::new(width, height, stride, data);
+        decoded.channel = channel;
+
+        match (tiling, mask) {
+            (Some(tiling), mask) => encode_tiles(tiling, &decoded, mask, encoded, offsets, coding),
+            (None, Some(mask)) => {
+                let rect = Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                };
+
+                // Masked pixels are replaced by their predecessor, which the decoder reproduces.
+                let filled = decoded.extract(&rect, Some(&mask.valid));
+                let mut decoded = Decoded::<P>::new(width, height, 1, Pixels::Bytes(&filled));
+                decoded.mask = Some(&mask.valid);
+
+                encode_channel(&mut decoded, encoded, coding)
+            }
+            (None, None) => encode_channel(&mut decoded, encoded, coding),
+        }
+    }
+
+    /// Returns the largest size of an encoded channel, before it is stored uncompressed.
+    fn max_channel_size<P: Pixel>(&self, tiling: Option<&Tiling>) -> usize {
+        let descriptor = &self.descriptor;
+
+        // Adaptive prediction stores a predictor for every row of every tile.
+        let predictors = match self.adaptive_prediction {
+            true => tiling.map_or(1, |tiling| tiling.columns) * descriptor.height as usize,
+            false => 0,
+        };
+
+        // Every opcode encodes at least one pixel and is at most one byte larger than a pixel.
+        descriptor.pixel_count() * (P::SIZE + 1) + predictors
+    }
+
+    /// Writes an encoded channel and records its size and checksum.
+    fn write_channel<W: Write>(
+        &self,
+        writer: &mut W,
+        channel: usize,
+        data: &[u8],
+        channels: &mut Channels,
+    ) -> Result<(), EncodeError> {
+        channels.sizes[channel] = data.len();
+
+        if self.checksums {
+            channels.checksums[channel] = Crc32::checksum(data);
+        }
+
+        writer.write_all(data)?;
+
+        Ok(())
+    }
+
+    /// Returns the size of the tiles, which span the whole width of the image for row groups.
+    fn tile_size(&self) -> Option<(u32, u32)> {
+        self.row_groups
+            .map(|rows| (self.descriptor.width.max(1), rows))
+            .or(self.tile_size)
+    }
+
+    /// Writes the header of the encoded DTM image.
+    ///
+    /// The checksums of the channels and the chunk section follow the channel size table, if they are enabled.
+    fn encode_header(&self, channels: &Channels, masks: &[Chunk]) -> Vec<u8> {
+        let descriptor = &self.descriptor;
+        let mut header = vec![0; DTM_HEADER_SIZE];
+
+        let mut features = 0;
+
+        if self.checksums {
+            features |= FEATURE_CHECKSUMS;
+        }
+
+        let mut chunks = Vec::new();
+
+        if let Some(georeference) = &self.georeference {
+            chunks.extend(georeference.encode());
+        }
+
+        for (channel, scale) in self.scales.iter().enumerate() {
+            if let Some(scale) = scale {
+                chunks.push(scale.encode(channel));
+            }
+        }
+
+        chunks.extend_from_slice(masks);
+        chunks.extend_from_slice(&self.chunks);
+
+        if !chunks.is_empty() {
+            features |= FEATURE_CHUNKS;
+        }
+
+        if self.tile_size().is_some() {
+            features |= FEATURE_TILES;
+        }
+
+        if self.adaptive_prediction {
+            features |= FEATURE_PREDICTORS;
+        }
+
+        if self.inter_channel_prediction {
+            features |= FEATURE_INTER_CHANNEL;
+        }
 
-            encoded = Encoded::new(&mut encoded.data[*channel_size..]);
-            decoded.next_channel();
+        if self.entropy_coding {
+            features |= FEATURE_ENTROPY;
         }
 
-        data[0..3].copy_from_slice(DTM_MAGIC);
-        data[3] = self.pixel_size as u8;
-        data[4..8].copy_from_slice(&(self.width as u32).to_be_bytes());
-        data[8..12].copy_from_slice(&(self.height as u32).to_be_bytes());
+        header[0..3].copy_from_slice(DTM_MAGIC);
+        header[4] = DTM_VERSION;
+        header[5] = descriptor.pixel_size as u8;
+        header[6] = descriptor.sample_format as u8;
+        header[7] = descriptor.channel_count as u8;
+        header[8..12].copy_from_slice(&features.to_be_bytes());
+        header[12..16].copy_from_slice(&descriptor.width.to_be_bytes());
+        header[16..20].copy_from_slice(&descriptor.height.to_be_bytes());
 
-        for i in 0..4 {
-            data[12 + i * 4..16 + i * 4].copy_from_slice(&(channel_sizes[i] as u32).to_be_bytes());
+        for (i, &channel_size) in channels.sizes.iter().enumerate() {
+            header[20 + i * 4..24 + i * 4].copy_from_slice(&(channel_size as u32).to_be_bytes());
         }
 
-        data.truncate(total_size);
+        if self.checksums {
+            for checksum in &channels.checksums[..descriptor.channel_count as usize] {
+                header.extend_from_slice(&checksum.to_be_bytes());
+            }
+        }
 
-        Ok(data)
+        if let Some((tile_width, tile_height)) = self.tile_size() {
+            header.extend_from_slice(&tile_width.to_be_bytes());
+            header.extend_from_slice(&tile_height.to_be_bytes());
+
+            for &offset in &channels.tile_offsets {
+                header.extend_from_slice(&(offset as u32).to_be_bytes());
+            }
+        }
+
+        if !chunks.is_empty() {
+            let start = header.len();
+            header.extend_from_slice(&[0; 4]);
+            encode_chunks(&chunks, &mut header);
+
+            let section_size = (header.len() - start - 4) as u32;
+            header[start..start + 4].copy_from_slice(&section_size.to_be_bytes());
+        }
+
+        header
     }
 }
 
-fn encode(encoded: &mut Encoded, decoded: &mut Decoded) {
+/// The sizes, checksums and tile offsets of the encoded channels, which are stored in the header.
+#[derive(Default)]
+struct Channels {
+    sizes: [usize; 4],
+    checksums: [u32; 4],
+    tile_offsets: Vec<usize>,
+}
+
+/// The pixels of an image, which are either interleaved or stored as a plane per channel.
+#[derive(Clone, Copy)]
+enum Layout<'a> {
+    Interleaved(&'a [u8]),
+    /// Interleaved unsigned 16 bit pixels.
+    Samples(&'a [u16]),
+    /// Interleaved signed 16 bit pixels.
+    Signed(&'a [i16]),
+    /// A plane of unsigned 16 bit pixels per channel.
+    Planar(&'a [&'a [u16]]),
+    /// Interleaved pixels, whose rows are the given number of bytes apart.
+    Strided(&'a [u8], usize),
+}
+
+impl<'a> Layout<'a> {
+    /// Returns the pixels containing the channel, the number of channels interleaved in them
+    /// and the position of the channel among them.
+    ///
+    /// The channels of strided images are copied into `plane`.
+    fn channel<'b>(
+        self,
+        descriptor: &DTM,
+        channel: usize,
+        plane: &'b mut Vec<u8>,
+    ) -> (Pixels<'b>, usize, usize)
+    where
+        'a: 'b,
+    {
+        let channel_count = descriptor.channel_count as usize;
+
+        match self {
+            Layout::Interleaved(data) => (Pixels::Bytes(data), channel_count, channel),
+            Layout::Samples(data) => (Pixels::Samples(data), channel_count, channel),
+            Layout::Signed(data) => (Pixels::Signed(data), channel_count, channel),
+            Layout::Planar(planes) => (Pixels::Samples(planes[channel]), 1, 0),
+            Layout::Strided(data, row_stride) => {
+                let pixel_size = descriptor.pixel_size as usize;
+                let row_size = descriptor.row_size();
+
+                *plane = data
+                    .chunks(row_stride)
+                    .flat_map(|row| {
+                        row[..row_size]
+                            .chunks_exact(pixel_size)
+                            .skip(channel)
+                            .step_by(channel_count)
+                    })
+                    .flatten()
+                    .copied()
+                    .collect();
+
+                (Pixels::Bytes(plane), 1, 0)
+            }
+        }
+    }
+}
+
+/// The pixels of a channel, which are either stored as little-endian bytes or as typed samples.
+#[derive(Clone, Copy)]
+enum Pixels<'a> {
+    Bytes(&'a [u8]),
+    Samples(&'a [u16]),
+    Signed(&'a [i16]),
+}
+
+impl Pixels<'_> {
+    /// Reads the pixel at `index`, which counts the pixels of all interleaved channels.
+    #[inline]
+    fn get<P: Pixel>(self, index: usize) -> P {
+        match self {
+            Pixels::Bytes(data) => P::read(&data[index * P::SIZE..]),
+            Pixels::Samples(data) => P::read(&data[index].to_le_bytes()),
+            Pixels::Signed(data) => P::read(&data[index].to_le_bytes()),
+        }
+    }
+
+    /// Returns the bits of the pixel at `index`, which is `pixel_size` bytes large.
+    fn value(self, index: usize, pixel_size: usize) -> u32 {
+        match self {
+            Pixels::Bytes(data) => {
+                let mut bytes = [0; 4];
+                bytes[..pixel_size].copy_from_slice(&data[index * pixel_size..][..pixel_size]);
+                u32::from_le_bytes(bytes)
+            }
+            Pixels::Samples(data) => data[index] as u32,
+            Pixels::Signed(data) => data[index] as u16 as u32,
+        }
+    }
+}
+
+/// Returns a plane holding the residuals of a channel to the preceding channel.
+///
+/// The residuals refer to the preceding channel as it is decoded,
+/// thus its masked pixels are replaced by its nodata value, or zero if it has none.
+fn channel_residuals<P: Pixel>(
+    descriptor: &DTM,
+    decoded: Layout<'_>,
+    channel: usize,
+    reference_mask: Option<&ChannelMask>,
+) -> Vec<u8> {
+    let (mut plane, mut reference_plane) = (Vec::new(), Vec::new());
+    let (data, stride, offset) = decoded.channel(descriptor, channel, &mut plane);
+    let (reference, reference_stride, reference_offset) =
+        decoded.channel(descriptor, channel - 1, &mut reference_plane);
+    let nodata = P::read(
+        &reference_mask
+            .and_then(|mask| mask.nodata)
+            .unwrap_or(0)
+            .to_le_bytes(),
+    );
+
+    let mut residuals = vec![0; descriptor.channel_size()];
+
+    for (index, residual) in residuals.chunks_exact_mut(P::SIZE).enumerate() {
+        let reference = match reference_mask {
+            Some(mask) if !mask.valid[index] => nodata,
+            _ => reference.get(index * reference_stride + reference_offset),
+        };
+
+        let pixel = data.get(index * stride + offset);
+        predictor::channel_residual(pixel, reference).write(residual);
+    }
+
+    residuals
+}
+
+/// Encodes the tiles of a channel one after another into `encoded`
+/// and returns the size of the encoded channel.
+///
+/// Every tile is encoded like a channel of an image of its own.
+fn encode_tiles<P: Pixel>(
+    tiling: &Tiling,
+    decoded: &Decoded<P>,
+    mask: Option<&ChannelMask>,
+    encoded: &mut [u8],
+    offsets: &mut [usize],
+    coding: Coding,
+) -> usize {
+    let mut size = 0;
+
+    for (tile, offset) in offsets.iter_mut().enumerate() {
+        let rect = tiling.rect(tile);
+        let mask = mask.map(|mask| mask.crop(&rect, decoded.width));
+        let valid = mask.as_ref().map(|mask| &mask.valid[..]);
+
+        let pixels = decoded.extract(&rect, valid);
+        let mut tile = Decoded::<P>::new(rect.width, rect.height, 1, Pixels::Bytes(&pixels));
+        tile.mask = valid;
+
+        *offset = size;
+        size += encode_channel(&mut tile, &mut encoded[size..], coding);
+    }
+
+    size
+}
+
+/// Encodes a channel into `encoded` and returns the size of the encoded channel.
+///
+/// With adaptive prediction, the predictor of each row is selected and stored in front of the opcodes.
+/// Channels, which do not compress, are stored uncompressed.
+fn encode_channel<P: Pixel>(decoded: &mut Decoded<P>, encoded: &mut [u8], coding: Coding) -> usize {
+    let mut encoded = Encoded::<P>::new(encoded);
+
+    if coding.predictors {
+        let predictors = predictor::select(
+            decoded.width,
+            decoded.height,
+            |index| decoded.get(index),
+            |index| decoded.mask.is_none_or(|mask| mask[index]),
+        );
+
+        for (byte, &predictor) in encoded.data.iter_mut().zip(&predictors) {
+            *byte = predictor as u8;
+        }
+
+        encoded.index = predictors.len();
+        decoded.predictors = Some(predictors);
+    }
+
+    match coding.entropy {
+        true => encode_residuals(&mut encoded, decoded),
+        false => encode(&mut encoded, decoded),
+    }
+
+    if encoded.index >= decoded.width * decoded.height * P::SIZE {
+        encoded.index = 0;
+        for index in 0..decoded.width * decoded.height {
+            decoded.get(index).write(&mut encoded.data[encoded.index..]);
+            encoded.index += P::SIZE;
+        }
+    };
+
+    encoded.index
+}
+
+/// Encodes the residuals of the valid pixels as Rice codes.
+///
+/// The residuals may not fit into `encoded`, in which case only the size is advanced,
+/// so that the channel is stored uncompressed.
+fn encode_residuals<P: Pixel>(encoded: &mut Encoded<P>, decoded: &mut Decoded<P>) {
+    let mut residuals = ResidualEncoder::default();
+
+    while !decoded.is_empty() {
+        if decoded.is_valid() {
+            residuals.push(decoded.current().diff(decoded.predict()));
+        }
+
+        decoded.index += 1;
+    }
+
+    let residuals = residuals.finish();
+
+    if let Some(data) = encoded
+        .data
+        .get_mut(encoded.index..encoded.index + residuals.len())
+    {
+        data.copy_from_slice(&residuals);
+    }
+
+    encoded.index += residuals.len();
+}
+
+fn encode<P: Pixel>(encoded: &mut Encoded<P>, decoded: &mut Decoded<P>) {
     while !decoded.is_empty() {
+        // Opcodes must not span masked pixels, so pending runs and diffs are finished before them.
+        if !decoded.is_valid() {
+            if encoded.run_length > 0 {
+                finish_run(encoded, decoded);
+            } else if let Some(previous_diff) = encoded.outstanding_diff.take() {
+                encoded.single_diff(previous_diff);
+            }
+
+            decoded.index += 1;
+            continue;
+        }
+
         let previous_pixel = decoded.previous();
         let pixel = decoded.current();
 
         if pixel == previous_pixel {
             encoded.run_length += 1;
 
-            if encoded.run_length == 63 {
+            if encoded.run_length == P::MAX_RUN_LENGTH {
                 finish_run(encoded, decoded);
             }
         } else {
@@ -130,7 +1183,7 @@ fn encode(encoded: &mut Encoded, decoded: &mut Decoded) {
                 finish_run(encoded, decoded);
             }
 
-            let diff = pixel as i32 - decoded.paeth() as i32;
+            let diff = pixel.diff(decoded.predict());
 
             if (-DOUBLE_DIFF_RANGE..DOUBLE_DIFF_RANGE).contains(&diff) {
                 if let Some(previous_diff) = encoded.outstanding_diff {
@@ -147,15 +1200,17 @@ fn encode(encoded: &mut Encoded, decoded: &mut Decoded) {
 
                 if (-SINGLE_DIFF_RANGE..SINGLE_DIFF_RANGE).contains(&diff) {
                     encoded.single_diff(diff);
-                } else if pixel == encoded.pixel_cache[(pixel % 64) as usize] {
+                } else if pixel == encoded.pixel_cache[pixel.cache_index()] {
                     encoded.cache(pixel);
+                } else if P::WIDE_DIFF && (-WIDE_DIFF_RANGE..WIDE_DIFF_RANGE).contains(&diff) {
+                    encoded.wide_diff(diff);
                 } else {
                     encoded.default(pixel);
                 }
             }
         }
 
-        encoded.pixel_cache[(pixel % 64) as usize] = pixel;
+        encoded.pixel_cache[pixel.cache_index()] = pixel;
         decoded.index += 1;
     }
 
@@ -219,13 +1274,13 @@ fn encode(encoded: &mut Encoded, decoded: &mut Decoded) {
 }
 
 #[inline]
-fn finish_run(encoded: &mut Encoded, decoded: &mut Decoded) {
+fn finish_run<P: Pixel>(encoded: &mut Encoded<P>, decoded: &mut Decoded<P>) {
     let mut run = true;
 
     if let Some(previous_diff) = encoded.outstanding_diff {
         if encoded.run_length == 1 {
             decoded.index -= 1;
-            let diff = decoded.current() as i32 - decoded.paeth() as i32;
+            let diff = decoded.current().diff(decoded.predict());
             decoded.index += 1;
 
             if (-DOUBLE_DIFF_RANGE..DOUBLE_DIFF_RANGE).contains(&previous_diff)
@@ -250,20 +1305,20 @@ fn finish_run(encoded: &mut Encoded, decoded: &mut Decoded) {
     encoded.run_length = 0;
 }
 
-struct Encoded<'a> {
+struct Encoded<'a, P> {
     data: &'a mut [u8],
-    pixel_cache: [u16; 64],
+    pixel_cache: [P; 64],
     outstanding_diff: Option<i32>,
     run_length: u8,
     index: usize,
 }
 
-impl<'a> Encoded<'a> {
+impl<'a, P: Pixel> Encoded<'a, P> {
     #[inline]
     fn new(data: &'a mut [u8]) -> Self {
         Self {
             data,
-            pixel_cache: [0; 64],
+            pixel_cache: [P::default(); 64],
             outstanding_diff: None,
             run_length: 0,
             index: 0,
@@ -271,8 +1326,8 @@ impl<'a> Encoded<'a> {
     }
 
     #[inline]
-    fn cache(&mut self, pixel: u16) {
-        self.data[self.index] = CACHE | (pixel % 64) as u8;
+    fn cache(&mut self, pixel: P) {
+        self.data[self.index] = CACHE | pixel.cache_index() as u8;
         self.index += 1;
         // unsafe { C_CACHE += 1 };
     }
@@ -296,36 +1351,45 @@ impl<'a> Encoded<'a> {
 
     #[inline]
     fn run_length(&mut self) {
-        self.data[self.index] = RUN_LENGTH | self.run_length - 1;
+        self.data[self.index] = RUN_LENGTH | (self.run_length - 1);
         self.index += 1;
         // unsafe { C_RUN_LENGTH += self.run_length as i32 };
         // unsafe { C_RUN_COUNT += 1 };
     }
 
     #[inline]
-    fn default(&mut self, pixel: u16) {
-        self.data[self.index..self.index + 3].copy_from_slice(&[
-            DEFAULT,
-            pixel as u8,
-            (pixel >> 8) as u8,
-        ]);
+    fn wide_diff(&mut self, diff: i32) {
+        self.data[self.index] = WIDE_DIFF;
+        self.data[self.index + 1..self.index + 3].copy_from_slice(&(diff as i16).to_le_bytes());
         self.index += 3;
+    }
+
+    #[inline]
+    fn default(&mut self, pixel: P) {
+        self.data[self.index] = DEFAULT;
+        pixel.write(&mut self.data[self.index + 1..]);
+        self.index += 1 + P::SIZE;
         // unsafe { C_DEFAULT += 1 };
     }
 }
 
-struct Decoded<'a> {
+struct Decoded<'a, P> {
     width: usize,
     height: usize,
     channel_count: usize,
-    data: &'a [u8],
+    data: Pixels<'a>,
     channel: usize,
     index: usize,
+    /// The validity mask of the channel, if it is masked.
+    mask: Option<&'a [bool]>,
+    /// The predictor of every row, if adaptive prediction is enabled.
+    predictors: Option<Vec<Predictor>>,
+    pixel: PhantomData<P>,
 }
 
-impl<'a> Decoded<'a> {
+impl<'a, P: Pixel> Decoded<'a, P> {
     #[inline]
-    pub fn new(width: usize, height: usize, channel_count: usize, data: &'a [u8]) -> Self {
+    pub fn new(width: usize, height: usize, channel_count: usize, data: Pixels<'a>) -> Self {
         Self {
             width,
             height,
@@ -333,62 +1397,546 @@ impl<'a> Decoded<'a> {
             data,
             channel: 0,
             index: 0,
+            mask: None,
+            predictors: None,
+            pixel: PhantomData,
         }
     }
 
     #[inline]
-    fn get(&self, index: usize) -> u16 {
-        let index = (index * self.channel_count + self.channel) << 1;
-        u16::from_le_bytes(self.data[index..index + 2].try_into().unwrap())
+    fn get(&self, index: usize) -> P {
+        self.data.get(index * self.channel_count + self.channel)
     }
 
     #[inline]
-    fn current(&self) -> u16 {
+    fn current(&self) -> P {
         self.get(self.index)
     }
 
     #[inline]
-    fn previous(&self) -> u16 {
+    fn previous(&self) -> P {
         if self.index == 0 {
-            0
+            P::default()
         } else {
             self.get(self.index - 1)
         }
     }
 
+    /// Predicts the current pixel with the predictor of its row,
+    /// or with the paeth filter if adaptive prediction is disabled.
     #[inline]
-    fn paeth(&mut self) -> u16 {
-        if self.index / self.width == 0 || self.index % self.width == 0 {
+    fn predict(&self) -> P {
+        match &self.predictors {
+            Some(predictors) => {
+                predictor::predict(predictors, self.index, self.width, |index| self.get(index))
+            }
+            None => self.paeth(),
+        }
+    }
+
+    #[inline]
+    fn paeth(&self) -> P {
+        if self.index / self.width == 0 || self.index.is_multiple_of(self.width) {
             self.previous()
         } else {
             let previous = self.get(self.index - 1);
             let above = self.get(self.index - self.width);
             let diagonal = self.get(self.index - self.width - 1);
 
-            let p = previous.wrapping_add(above).wrapping_sub(diagonal);
-
-            let diff_p_previous = p.abs_diff(previous);
-            let diff_p_above = p.abs_diff(above);
-            let diff_p_diagonal = p.abs_diff(diagonal);
-
-            if diff_p_previous <= diff_p_above && diff_p_previous <= diff_p_diagonal {
-                previous
-            } else if diff_p_above <= diff_p_diagonal {
-                above
-            } else {
-                diagonal
-            }
+            P::paeth(previous, above, diagonal)
         }
     }
 
+    #[inline]
+    fn is_valid(&self) -> bool {
+        self.mask.is_none_or(|mask| mask[self.index])
+    }
+
     #[inline]
     fn is_empty(&self) -> bool {
         self.index == self.width * self.height
     }
 
-    #[inline]
-    fn next_channel(&mut self) {
-        self.channel += 1;
-        self.index = 0;
+    /// Returns the pixels of the channel inside the rectangle row by row,
+    /// with every masked pixel replaced by its predecessor.
+    ///
+    /// The mask `valid` covers the pixels inside the rectangle.
+    fn extract(&self, rect: &Rect, valid: Option<&[bool]>) -> Vec<u8> {
+        let mut extracted = vec![0; rect.pixel_count() * P::SIZE];
+        let mut pixel = P::default();
+
+        for (index, bytes) in extracted.chunks_exact_mut(P::SIZE).enumerate() {
+            if valid.is_none_or(|valid| valid[index]) {
+                pixel = self.get(rect.image_index(index, self.width));
+            }
+
+            pixel.write(bytes);
+        }
+
+        extracted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode::DecodeError,
+        testing::{descriptor, terrain},
+    };
+
+    /// Returns the 16 bit samples of little-endian pixels.
+    fn samples(decoded: &[u8]) -> Vec<u16> {
+        decoded
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect()
+    }
+
+    #[test]
+    fn roundtrips_u16_samples() {
+        for channel_count in 1..=4 {
+            let descriptor = descriptor(2, SampleFormat::Unsigned, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let pixels = samples(&decoded);
+
+            for encoder in [
+                Encoder::new(descriptor),
+                Encoder::new(descriptor).nodata(0, 500).tiles(16, 8),
+                Encoder::new(descriptor).inter_channel_prediction(true),
+            ] {
+                let encoded = encoder.encode_u16(&pixels).unwrap();
+                assert_eq!(encoded, encoder.encode_alloc(&decoded).unwrap());
+
+                let (_, decoded_pixels) = DTM::decode_u16_alloc(&encoded).unwrap();
+                assert_eq!(
+                    decoded_pixels,
+                    samples(&DTM::decode_alloc(&encoded).unwrap().1)
+                );
+
+                let mut into = vec![0; pixels.len() + 3];
+                assert_eq!(
+                    DTM::decode_into_u16(&encoded, &mut into).unwrap(),
+                    descriptor
+                );
+                assert_eq!(into[..pixels.len()], decoded_pixels);
+                assert_eq!(into[pixels.len()..], [0; 3]);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_u16_samples() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 37, 29);
+        let pixels = samples(&terrain(&descriptor));
+        let encoded = descriptor.encode_u16(&pixels).unwrap();
+
+        assert!(matches!(
+            descriptor.encode_u16(&pixels[1..]),
+            Err(EncodeError::InsufficientInputData)
+        ));
+        assert!(matches!(
+            DTM::decode_into_u16(&encoded, &mut vec![0; pixels.len() - 1]),
+            Err(DecodeError::InsufficientOutputBuffer)
+        ));
+
+        for (pixel_size, sample_format) in [
+            (2, SampleFormat::Signed),
+            (1, SampleFormat::Unsigned),
+            (4, SampleFormat::Unsigned),
+        ] {
+            let other = DTM {
+                pixel_size,
+                sample_format,
+                ..descriptor
+            };
+
+            assert!(matches!(
+                other.encode_u16(&pixels),
+                Err(EncodeError::SampleTypeMismatch)
+            ));
+
+            let encoded = other.encode_alloc(&terrain(&other)).unwrap();
+            assert!(matches!(
+                DTM::decode_u16_alloc(&encoded),
+                Err(DecodeError::SampleTypeMismatch)
+            ));
+            assert!(matches!(
+                DTM::decode_into_u16(&encoded, &mut vec![0; pixels.len() * 2]),
+                Err(DecodeError::SampleTypeMismatch)
+            ));
+        }
+    }
+
+    #[test]
+    fn reads_big_endian_pixels() {
+        for (pixel_size, sample_format) in [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ] {
+            let descriptor = descriptor(pixel_size, sample_format, 2, 37, 29);
+            let decoded = terrain(&descriptor);
+            let swapped: Vec<u8> = decoded
+                .chunks_exact(pixel_size as usize)
+                .flat_map(|pixel| pixel.iter().rev())
+                .copied()
+                .collect();
+
+            let encoder = Encoder::new(descriptor).big_endian(true);
+            let encoded = encoder.encode_alloc(&swapped).unwrap();
+
+            assert_eq!(encoded, descriptor.encode_alloc(&decoded).unwrap());
+            assert_eq!(DTM::decode_alloc(&encoded).unwrap().1, decoded);
+        }
+
+        // Typed samples are in native order, so the option does not apply to them.
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let pixels = samples(&terrain(&descriptor));
+        let encoded = Encoder::new(descriptor)
+            .big_endian(true)
+            .encode_u16(&pixels)
+            .unwrap();
+
+        assert_eq!(DTM::decode_u16_alloc(&encoded).unwrap().1, pixels);
+    }
+
+    #[test]
+    fn roundtrips_planes() {
+        for channel_count in 2..=4 {
+            let descriptor = descriptor(2, SampleFormat::Unsigned, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let pixels = samples(&decoded);
+            let planes: Vec<Vec<u16>> = (0..channel_count as usize)
+                .map(|channel| {
+                    pixels
+                        .iter()
+                        .skip(channel)
+                        .step_by(channel_count as usize)
+                        .copied()
+                        .collect()
+                })
+                .collect();
+            let planes: Vec<&[u16]> = planes.iter().map(Vec::as_slice).collect();
+
+            for encoder in [
+                Encoder::new(descriptor),
+                Encoder::new(descriptor).nodata(1, 540).tiles(16, 8),
+                Encoder::new(descriptor).inter_channel_prediction(true),
+            ] {
+                let encoded = encoder.encode_planar(&planes).unwrap();
+                assert_eq!(encoded, encoder.encode_alloc(&decoded).unwrap());
+
+                let (_, interleaved) = DTM::decode_u16_alloc(&encoded).unwrap();
+                let (_, decoded_planes) = DTM::decode_planar(&encoded).unwrap();
+
+                assert_eq!(decoded_planes.len(), channel_count as usize);
+
+                for (channel, plane) in decoded_planes.iter().enumerate() {
+                    let expected: Vec<u16> = interleaved
+                        .iter()
+                        .skip(channel)
+                        .step_by(channel_count as usize)
+                        .copied()
+                        .collect();
+                    assert_eq!(*plane, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_planes() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 3, 37, 29);
+        let plane = vec![7; descriptor.pixel_count()];
+
+        assert!(matches!(
+            descriptor.encode_planar(&[&plane, &plane]),
+            Err(EncodeError::PlaneCountMismatch)
+        ));
+        assert!(matches!(
+            descriptor.encode_planar(&[&plane, &plane, &plane, &plane]),
+            Err(EncodeError::PlaneCountMismatch)
+        ));
+        assert!(matches!(
+            descriptor.encode_planar(&[&plane, &plane[1..], &plane]),
+            Err(EncodeError::InsufficientInputData)
+        ));
+
+        let signed = DTM {
+            sample_format: SampleFormat::Signed,
+            ..descriptor
+        };
+        assert!(matches!(
+            signed.encode_planar(&[&plane, &plane, &plane]),
+            Err(EncodeError::SampleTypeMismatch)
+        ));
+
+        let encoded = signed.encode_alloc(&terrain(&signed)).unwrap();
+        assert!(matches!(
+            DTM::decode_planar(&encoded),
+            Err(DecodeError::SampleTypeMismatch)
+        ));
+    }
+
+    #[test]
+    fn roundtrips_strided_windows() {
+        for (pixel_size, sample_format, channel_count) in [
+            (1, SampleFormat::Unsigned, 1),
+            (2, SampleFormat::Unsigned, 3),
+            (4, SampleFormat::Float, 2),
+        ] {
+            let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let row_size = descriptor.row_size();
+
+            // The window starts at (5, 3) of a mosaic, which is 11 pixels wider and 6 rows higher.
+            let texel_size = (pixel_size * channel_count) as usize;
+            let row_stride = row_size + 11 * texel_size;
+            let offset = 3 * row_stride + 5 * texel_size;
+            let mut mosaic: Vec<u8> = (0..row_stride * 35).map(|index| index as u8).collect();
+
+            for (row, pixels) in decoded.chunks_exact(row_size).enumerate() {
+                let start = offset + row * row_stride;
+                mosaic[start..start + row_size].copy_from_slice(pixels);
+            }
+
+            for encoder in [
+                Encoder::new(descriptor),
+                Encoder::new(descriptor).tiles(16, 8),
+            ] {
+                let encoded = encoder.encode_strided(&mosaic, offset, row_stride).unwrap();
+                assert_eq!(encoded, encoder.encode_alloc(&decoded).unwrap());
+
+                let mut window: Vec<u8> = (0..mosaic.len()).map(|index| !index as u8).collect();
+                let expected = {
+                    let mut expected = window.clone();
+
+                    for (row, pixels) in decoded.chunks_exact(row_size).enumerate() {
+                        let start = offset + row * row_stride;
+                        expected[start..start + row_size].copy_from_slice(pixels);
+                    }
+
+                    expected
+                };
+
+                DTM::decode_strided(&encoded, &mut window, offset, row_stride).unwrap();
+                assert_eq!(window, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_strided_windows_out_of_bounds() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let decoded = terrain(&descriptor);
+        let row_size = descriptor.row_size();
+        let row_stride = row_size + 8;
+        let mosaic = vec![0; row_stride * 29];
+        let encoded = descriptor.encode_alloc(&decoded).unwrap();
+
+        for (offset, row_stride) in [
+            (9, row_stride),
+            (mosaic.len() + 1, row_stride),
+            (usize::MAX, row_stride),
+            (0, row_stride + 1),
+            (0, usize::MAX),
+        ] {
+            assert!(matches!(
+                descriptor.encode_strided(&mosaic, offset, row_stride),
+                Err(EncodeError::InsufficientInputData)
+            ));
+
+            let mut window = mosaic.clone();
+            assert!(matches!(
+                DTM::decode_strided(&encoded, &mut window, offset, row_stride),
+                Err(DecodeError::InsufficientOutputBuffer)
+            ));
+            assert_eq!(window, mosaic);
+        }
+
+        assert!(matches!(
+            descriptor.encode_strided(&mosaic, 0, row_size - 1),
+            Err(EncodeError::InvalidStride)
+        ));
+        assert!(matches!(
+            DTM::decode_strided(&encoded, &mut mosaic.clone(), 0, row_size - 1),
+            Err(DecodeError::InvalidStride)
+        ));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn encodes_channels_in_parallel_like_the_serial_encoder() {
+        for channel_count in 2..=4 {
+            let descriptor = descriptor(2, SampleFormat::Unsigned, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let layout = Layout::Interleaved(&decoded);
+
+            for encoder in [
+                Encoder::new(descriptor).checksums(true),
+                Encoder::new(descriptor).nodata(1, 541).tiles(16, 8),
+                Encoder::new(descriptor)
+                    .inter_channel_prediction(true)
+                    .adaptive_prediction(true),
+                Encoder::new(descriptor).entropy_coding(true).row_groups(4),
+            ] {
+                let encoded = encoder.encode_alloc(&decoded).unwrap();
+                let header = DTM::decode_header(&encoded).unwrap();
+
+                let masks = encoder.masks(layout).unwrap();
+                let tiling = encoder.tile_size().map(|(tile_width, tile_height)| {
+                    Tiling::new(&descriptor, tile_width, tile_height)
+                });
+                let tile_count = tiling.as_ref().map_or(0, Tiling::tile_count);
+                let mut data = vec![0; encoder.max_channel_size::<u16>(tiling.as_ref())];
+                let mut start = header.header_size;
+
+                // Encodes the channels one after another on the current thread.
+                for channel in 0..channel_count as usize {
+                    let mut offsets = vec![0; tile_count];
+                    let size = encoder.encode_channel_of::<u16>(
+                        layout,
+                        channel,
+                        &masks,
+                        tiling.as_ref(),
+                        &mut data,
+                        &mut offsets,
+                    );
+
+                    assert_eq!(header.channel_sizes[channel], size);
+                    assert_eq!(encoded[start..start + size], data[..size]);
+
+                    for (tile, &offset) in offsets.iter().enumerate() {
+                        assert_eq!(
+                            header.tiles.as_ref().unwrap().range(channel, tile).start,
+                            offset
+                        );
+                    }
+
+                    start += size;
+                }
+
+                assert_eq!(start, encoded.len());
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_to_writer() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 3, 37, 29);
+        let decoded = terrain(&descriptor);
+
+        for encoder in [
+            Encoder::new(descriptor),
+            Encoder::new(descriptor).checksums(true).tiles(16, 8),
+        ] {
+            let expected = encoder.encode_alloc(&decoded).unwrap();
+
+            let mut writer = Cursor::new(Vec::new());
+            let size = encoder.encode_to_writer(&mut writer, &decoded).unwrap();
+            assert_eq!(size, expected.len());
+            assert_eq!(writer.position(), size as u64);
+            assert_eq!(writer.into_inner(), expected);
+
+            // The header is patched relative to the start position,
+            // and the bytes before it as well as the ones after the image are kept.
+            let mut writer = Cursor::new(vec![0xAA; 7 + expected.len() + 5]);
+            writer.set_position(7);
+
+            let size = encoder.encode_to_writer(&mut writer, &decoded).unwrap();
+            assert_eq!(size, expected.len());
+            assert_eq!(writer.position(), 7 + size as u64);
+
+            let written = writer.into_inner();
+            assert_eq!(written[..7], [0xAA; 7]);
+            assert_eq!(written[7..7 + size], expected);
+            assert_eq!(written[7 + size..], [0xAA; 5]);
+        }
+    }
+
+    #[test]
+    fn roundtrips_i16_samples() {
+        for channel_count in 1..=3 {
+            let descriptor = descriptor(2, SampleFormat::Signed, channel_count, 37, 29);
+            let decoded = terrain(&descriptor);
+            let pixels: Vec<i16> = samples(&decoded)
+                .iter()
+                .map(|&pixel| pixel as i16)
+                .collect();
+
+            for encoder in [
+                Encoder::new(descriptor),
+                Encoder::new(descriptor)
+                    .nodata(0, -9999i16 as u16 as u32)
+                    .tiles(16, 8),
+                Encoder::new(descriptor).inter_channel_prediction(true),
+            ] {
+                let encoded = encoder.encode_i16(&pixels).unwrap();
+                assert_eq!(encoded, encoder.encode_alloc(&decoded).unwrap());
+
+                let (_, decoded_pixels) = DTM::decode_i16(&encoded).unwrap();
+                assert_eq!(decoded_pixels, pixels);
+
+                let mut into = vec![0; pixels.len() + 3];
+                assert_eq!(
+                    DTM::decode_into_i16(&encoded, &mut into).unwrap(),
+                    descriptor
+                );
+                assert_eq!(into[..pixels.len()], pixels);
+                assert_eq!(into[pixels.len()..], [0; 3]);
+            }
+        }
+
+        // Negative values and the extremes of the value range survive the sign flip.
+        let descriptor = descriptor(2, SampleFormat::Signed, 1, 4, 2);
+        let pixels = [i16::MIN, -9999, -1, 0, 1, 9999, i16::MAX, -2];
+        let encoded = descriptor.encode_i16(&pixels).unwrap();
+
+        assert_eq!(DTM::decode_i16(&encoded).unwrap().1, pixels);
+    }
+
+    #[test]
+    fn rejects_mismatched_i16_samples() {
+        let descriptor = descriptor(2, SampleFormat::Signed, 2, 37, 29);
+        let pixels = vec![-3; descriptor.image_size() / 2];
+        let encoded = descriptor.encode_i16(&pixels).unwrap();
+
+        assert!(matches!(
+            descriptor.encode_i16(&pixels[1..]),
+            Err(EncodeError::InsufficientInputData)
+        ));
+        assert!(matches!(
+            DTM::decode_into_i16(&encoded, &mut vec![0; pixels.len() - 1]),
+            Err(DecodeError::InsufficientOutputBuffer)
+        ));
+
+        for (pixel_size, sample_format) in [
+            (2, SampleFormat::Unsigned),
+            (1, SampleFormat::Signed),
+            (4, SampleFormat::Signed),
+        ] {
+            let other = DTM {
+                pixel_size,
+                sample_format,
+                ..descriptor
+            };
+
+            assert!(matches!(
+                other.encode_i16(&pixels),
+                Err(EncodeError::SampleTypeMismatch)
+            ));
+
+            let encoded = other.encode_alloc(&terrain(&other)).unwrap();
+            assert!(matches!(
+                DTM::decode_i16(&encoded),
+                Err(DecodeError::SampleTypeMismatch)
+            ));
+            assert!(matches!(
+                DTM::decode_into_i16(&encoded, &mut vec![0; pixels.len() * 2]),
+                Err(DecodeError::SampleTypeMismatch)
+            ));
+        }
     }
 }
@@ -0,0 +1,141 @@
+//! Information about an encoded image, which is read from its header alone.
+
+use crate::{
+    decode::{DecodeError, Header},
+    reader::read_header_without_chunks,
+    DTM,
+};
+use std::{fs::File, io::Read, path::Path};
+
+/// The descriptor and the sizes of an encoded DTM image.
+///
+/// Only the header is read, so this is much cheaper than decoding the image.
+/// The chunks of the header, e.g. the metadata and the validity masks, are skipped as well,
+/// thus the channel sizes are not checked against the masks until the image is decoded.
+///
+/// ```no_run
+/// # use dtm::info::DtmInfo;
+/// let info = DtmInfo::from_file("image.dtm").unwrap();
+/// println!("{}x{}", info.descriptor.width, info.descriptor.height);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct DtmInfo {
+    pub descriptor: DTM,
+    /// The size of the encoded data of each channel in bytes, which is zero for missing channels.
+    pub channel_sizes: [usize; 4],
+    /// Whether each channel is stored uncompressed, because it did not compress.
+    pub raw: [bool; 4],
+    /// The size of the header including its optional sections in bytes.
+    pub header_size: usize,
+    /// The size of the encoded image including the header in bytes.
+    pub total_size: usize,
+    /// Whether the channels store the differences to the preceding channel,
+    /// for which [`DtmReader`](crate::reader::DtmReader) keeps up to a whole channel in memory.
+    pub inter_channel: bool,
+}
+
+impl DtmInfo {
+    /// Reads the information from the header at the start of an encoded image.
+    ///
+    /// Neither the chunks nor the channel data have to be present in `encoded`.
+    pub fn from_slice(encoded: &[u8]) -> Result<Self, DecodeError> {
+        let Header {
+            descriptor,
+            channel_sizes,
+            header_size,
+            total_size,
+            inter_channel,
+            ..
+        } = DTM::decode_header_without_chunks(encoded)?;
+
+        let mut raw = [false; 4];
+
+        for (raw, &size) in raw
+            .iter_mut()
+            .zip(&channel_sizes)
+            .take(descriptor.channel_count as usize)
+        {
+            *raw = size == descriptor.channel_size();
+        }
+
+        Ok(Self {
+            descriptor,
+            channel_sizes,
+            raw,
+            header_size,
+            total_size,
+            inter_channel: inter_channel && descriptor.channel_count > 1,
+        })
+    }
+
+    /// Reads the information from a reader, which is left positioned at the start of the chunks,
+    /// or after the header if the image has no chunk section.
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, DecodeError> {
+        Self::from_slice(&read_header_without_chunks(&mut reader)?)
+    }
+
+    /// Reads the information from a file, of which only the header without its chunks is read.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, DecodeError> {
+        Self::from_reader(File::open(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{RegressionFile, REGRESSION_FILES};
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_regression_files() {
+        for &RegressionFile { name, encoded, .. } in &REGRESSION_FILES {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("tests/data")
+                .join(name);
+            let header = DTM::decode_header(encoded).unwrap();
+
+            let info = DtmInfo::from_file(&path).unwrap();
+            assert_eq!(info.descriptor, header.descriptor, "{name}");
+            assert_eq!(info.channel_sizes, header.channel_sizes, "{name}");
+            assert_eq!(info.header_size, header.header_size, "{name}");
+            assert_eq!(info.total_size, encoded.len(), "{name}");
+            assert_eq!(DtmInfo::from_slice(encoded).unwrap(), info, "{name}");
+
+            // Only the header up to the chunks is read.
+            let mut reader = Cursor::new(encoded);
+            assert_eq!(DtmInfo::from_reader(&mut reader).unwrap(), info, "{name}");
+
+            let position = reader.position() as usize;
+            assert_eq!(
+                position,
+                header.chunks.start.min(header.header_size),
+                "{name}"
+            );
+            assert_eq!(
+                DtmInfo::from_slice(&encoded[..position]).unwrap(),
+                info,
+                "{name}"
+            );
+            assert!(matches!(
+                DtmInfo::from_slice(&encoded[..position - 1]),
+                Err(DecodeError::InsufficientHeaderData)
+            ));
+        }
+    }
+
+    #[test]
+    fn skips_the_chunks() {
+        let encoded = include_bytes!("../tests/data/chunks.dtm");
+        let header = DTM::decode_header(encoded).unwrap();
+        assert!(!header.chunks.is_empty());
+
+        // Corrupt chunks are not noticed, as they are never read.
+        let mut corrupt = encoded.to_vec();
+        corrupt[header.chunks.clone()].fill(0xFF);
+        assert!(DTM::decode_header(&corrupt).is_err());
+
+        let info = DtmInfo::from_reader(corrupt.as_slice()).unwrap();
+        assert_eq!(info, DtmInfo::from_slice(encoded).unwrap());
+        assert_eq!(info.header_size, header.header_size);
+    }
+}
@@ -1,7 +1,24 @@
+pub mod chunk;
+mod crc;
 pub mod decode;
 pub mod encode;
+pub mod info;
+mod mask;
+pub mod metadata;
+mod pixel;
+mod predictor;
+pub mod reader;
+mod rice;
+#[cfg(test)]
+mod testing;
+mod tile;
 
-pub const DTM_HEADER_SIZE: usize = 28;
+/// The size of the header in bytes.
+pub const DTM_HEADER_SIZE: usize = 36;
+/// The size of the header of images, which were written before the header was versioned.
+pub const DTM_LEGACY_HEADER_SIZE: usize = 28;
+/// The version of the format written by the encoder.
+pub const DTM_VERSION: u8 = 1;
 pub const DTM_MAGIC: &[u8] = "dtm".as_bytes();
 
 /// The descriptor of a DTM image.
@@ -10,6 +27,7 @@ pub const DTM_MAGIC: &[u8] = "dtm".as_bytes();
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct DTM {
     pub pixel_size: u32,
+    pub sample_format: SampleFormat,
     pub channel_count: u32,
     pub width: u32,
     pub height: u32,
@@ -19,14 +37,101 @@ impl DTM {
     /// Returns the size of the decoded image in bytes .
     #[inline]
     pub fn image_size(&self) -> usize {
-        (self.pixel_size * self.channel_count * self.width * self.height) as usize
+        self.pixel_size as usize * self.channel_count as usize * self.pixel_count()
     }
 
     /// Returns the size of a channel of the decoded image in bytes .
     #[inline]
     pub fn channel_size(&self) -> usize {
-        (self.pixel_size * self.width * self.height) as usize
+        self.pixel_size as usize * self.pixel_count()
     }
+
+    /// Returns the number of pixels of a channel of the decoded image.
+    #[inline]
+    pub fn pixel_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    /// Returns the size of a row of the decoded image in bytes.
+    #[inline]
+    pub fn row_size(&self) -> usize {
+        self.pixel_size as usize * self.channel_count as usize * self.width as usize
+    }
+
+    /// Returns the number of bytes the rows of the decoded image span inside a larger buffer,
+    /// in which they are `row_size` bytes long and `row_stride` bytes apart,
+    /// or `None` if it exceeds the addressable memory.
+    #[inline]
+    pub(crate) fn strided_size(&self, row_size: usize, row_stride: usize) -> Option<usize> {
+        match self.height as usize {
+            0 => Some(0),
+            height => (height - 1).checked_mul(row_stride)?.checked_add(row_size),
+        }
+    }
+}
+
+/// The interpretation of the pixel values of a DTM image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SampleFormat {
+    /// Unsigned integers.
+    #[default]
+    Unsigned,
+    /// IEEE 754 floating point numbers, which require a pixel size of 4 bytes.
+    ///
+    /// The bits of the floats are preserved exactly, including the sign of zero and NaN payloads.
+    Float,
+    /// Two's complement signed integers.
+    Signed,
+}
+
+impl SampleFormat {
+    /// Returns whether the sample format supports pixels of the given size.
+    #[inline]
+    pub fn supports(self, pixel_size: u32) -> bool {
+        match self {
+            SampleFormat::Unsigned | SampleFormat::Signed => matches!(pixel_size, 1 | 2 | 4),
+            SampleFormat::Float => pixel_size == 4,
+        }
+    }
+}
+
+/// The feature flags understood by the decoder.
+///
+/// Images using any other feature are rejected, as they can not be decoded correctly.
+pub(crate) const SUPPORTED_FEATURES: u32 = FEATURE_CHECKSUMS
+    | FEATURE_CHUNKS
+    | FEATURE_TILES
+    | FEATURE_PREDICTORS
+    | FEATURE_INTER_CHANNEL
+    | FEATURE_ENTROPY;
+
+/// The header stores a CRC-32 checksum of every encoded channel after the channel size table.
+pub(crate) const FEATURE_CHECKSUMS: u32 = 1 << 0;
+
+/// The header ends with a section of metadata chunks, which starts with its size (u32).
+pub(crate) const FEATURE_CHUNKS: u32 = 1 << 1;
+
+/// The channels are split into independently encoded tiles,
+/// whose size and offset table precede the chunk section.
+pub(crate) const FEATURE_TILES: u32 = 1 << 2;
+
+/// Every compressed channel and tile starts with the predictor of each of its rows,
+/// which replaces the paeth filter.
+pub(crate) const FEATURE_PREDICTORS: u32 = 1 << 3;
+
+/// Every channel but the first stores the differences to the decoded preceding channel.
+pub(crate) const FEATURE_INTER_CHANNEL: u32 = 1 << 4;
+
+/// The residuals of the compressed channels and tiles are Rice coded instead of encoded as opcodes.
+pub(crate) const FEATURE_ENTROPY: u32 = 1 << 5;
+
+/// The coding of the compressed channels and tiles, which is selected by the feature flags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Coding {
+    /// Whether the channels and tiles start with the predictors of their rows.
+    pub(crate) predictors: bool,
+    /// Whether the residuals are Rice coded instead of encoded as opcodes.
+    pub(crate) entropy: bool,
 }
 
 pub(crate) const CACHE: u8 = 0b00000000;
@@ -37,6 +142,7 @@ pub(crate) const DOUBLE_DIFF: u8 = 0b10000000;
 pub(crate) const DOUBLE_DIFF_END: u8 = 0b10111111;
 pub(crate) const RUN_LENGTH: u8 = 0b11000000;
 pub(crate) const RUN_LENGTH_END: u8 = 0b11111110;
+pub(crate) const WIDE_DIFF: u8 = 0b11111110;
 pub(crate) const DEFAULT: u8 = 0b11111111;
 
 pub(crate) const MASK_6BIT: u8 = 0b00111111;
@@ -44,3 +150,4 @@ pub(crate) const MASK_3BIT: u8 = 0b00000111;
 
 pub(crate) const SINGLE_DIFF_RANGE: i32 = 32;
 pub(crate) const DOUBLE_DIFF_RANGE: i32 = 4;
+pub(crate) const WIDE_DIFF_RANGE: i32 = 32768;
@@ -0,0 +1,345 @@
+//! Validity masks, which mark the pixels of a channel that hold no data.
+//!
+//! Masked pixels are skipped by the encoder and cost nothing but their share of the mask.
+//! The predictor sees every masked pixel as a copy of the preceding pixel,
+//! and no opcode spans a masked pixel.
+
+use crate::{
+    chunk::{Chunk, ChunkId, Chunks},
+    decode::DecodeError,
+    tile::Rect,
+    DTM,
+};
+
+impl ChunkId {
+    /// The validity mask of a channel, stored as the channel (u8), a flag byte
+    /// (bit 0 = the channel has a nodata value), the nodata value (u32),
+    /// the kind of the mask (0 = bitmap, 1 = run lengths) and the mask itself.
+    pub const MASK: ChunkId = ChunkId(*b"MASK");
+}
+
+/// The mask is stored as a bitmap of one bit per pixel, least significant bit first.
+const BITMAP: u8 = 0;
+/// The mask is stored as the lengths of alternating runs of valid and masked pixels (LEB128),
+/// starting with a run of valid pixels.
+const RUNS: u8 = 1;
+
+/// The size of the mask chunk without the mask itself.
+const MASK_HEADER_SIZE: usize = 7;
+
+/// The validity mask of a channel and the value written to its masked pixels.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ChannelMask {
+    /// Whether each pixel of the channel is valid.
+    pub(crate) valid: Vec<bool>,
+    /// The bits of the pixel value, which marks masked pixels.
+    pub(crate) nodata: Option<u32>,
+}
+
+impl ChannelMask {
+    /// Returns the chunk storing the mask of the channel.
+    ///
+    /// The mask is stored as a bitmap or as run lengths, whichever is smaller.
+    pub(crate) fn encode(&self, channel: usize) -> Chunk {
+        let mut data = vec![channel as u8, self.nodata.is_some() as u8];
+        data.extend_from_slice(&self.nodata.unwrap_or(0).to_be_bytes());
+
+        let runs = encode_runs(&self.valid);
+
+        if runs.len() < self.valid.len().div_ceil(8) {
+            data.push(RUNS);
+            data.extend_from_slice(&runs);
+        } else {
+            data.push(BITMAP);
+            data.extend(self.valid.chunks(8).map(|bits| {
+                bits.iter()
+                    .enumerate()
+                    .fold(0, |byte, (bit, &valid)| byte | (valid as u8) << bit)
+            }));
+        }
+
+        Chunk::new(ChunkId::MASK, data)
+    }
+
+    /// Parses the channel and the nodata value out of a mask chunk, without decoding the mask.
+    pub(crate) fn decode_header(data: &[u8]) -> Result<(usize, Option<u32>), DecodeError> {
+        if data.len() < MASK_HEADER_SIZE || data[0] >= 4 || data[1] > 1 {
+            return Err(DecodeError::InvalidChunk(ChunkId::MASK));
+        }
+
+        let nodata = u32::from_be_bytes(data[2..6].try_into().unwrap());
+
+        Ok((data[0] as usize, (data[1] == 1).then_some(nodata)))
+    }
+
+    /// Returns the channel of a mask chunk and the number of pixels it masks, without decoding the mask.
+    pub(crate) fn masked_count(
+        data: &[u8],
+        pixel_count: usize,
+    ) -> Result<(usize, usize), DecodeError> {
+        let (channel, _) = Self::decode_header(data)?;
+        let mut payload = &data[MASK_HEADER_SIZE..];
+
+        let masked = match data[6] {
+            BITMAP if payload.len() == pixel_count.div_ceil(8) => {
+                let valid = payload.iter().map(|byte| byte.count_ones() as usize);
+                pixel_count.saturating_sub(valid.sum())
+            }
+            RUNS => {
+                let (mut total, mut masked) = (0u64, 0u64);
+                let mut state = true;
+
+                while !payload.is_empty() {
+                    let length = decode_varint(&mut payload)
+                        .ok_or(DecodeError::InvalidChunk(ChunkId::MASK))?;
+
+                    total = total.saturating_add(length);
+
+                    if !state {
+                        masked = masked.saturating_add(length);
+                    }
+
+                    state = !state;
+                }
+
+                if total != pixel_count as u64 {
+                    return Err(DecodeError::InvalidChunk(ChunkId::MASK));
+                }
+
+                masked as usize
+            }
+            _ => return Err(DecodeError::InvalidChunk(ChunkId::MASK)),
+        };
+
+        Ok((channel, masked))
+    }
+
+    fn decode(data: &[u8], pixel_count: usize) -> Result<(usize, Self), DecodeError> {
+        let invalid = DecodeError::InvalidChunk(ChunkId::MASK);
+
+        let (channel, nodata) = Self::decode_header(data)?;
+        let payload = &data[MASK_HEADER_SIZE..];
+
+        let valid = match data[6] {
+            BITMAP if payload.len() == pixel_count.div_ceil(8) => (0..pixel_count)
+                .map(|index| payload[index / 8] >> (index % 8) & 1 == 1)
+                .collect(),
+            RUNS => decode_runs(payload, pixel_count).ok_or(invalid)?,
+            _ => return Err(invalid),
+        };
+
+        Ok((channel, Self { valid, nodata }))
+    }
+
+    /// Returns the mask of the pixels inside the rectangle of an image of the given width.
+    pub(crate) fn crop(&self, rect: &Rect, width: usize) -> Self {
+        Self {
+            valid: (0..rect.pixel_count())
+                .map(|index| self.valid[rect.image_index(index, width)])
+                .collect(),
+            nodata: self.nodata,
+        }
+    }
+
+    /// Overwrites the masked pixels, starting with the pixel at index `start`,
+    /// with the nodata value, or zero if there is none.
+    pub(crate) fn fill<'a>(&self, pixels: impl Iterator<Item = &'a mut [u8]>, start: usize) {
+        let nodata = self.nodata.unwrap_or(0).to_le_bytes();
+
+        for (pixel, &valid) in pixels.zip(&self.valid[start..]) {
+            if !valid {
+                pixel.copy_from_slice(&nodata[..pixel.len()]);
+            }
+        }
+    }
+}
+
+/// The validity masks of the channels of an image.
+pub(crate) type Masks = [Option<ChannelMask>; 4];
+
+/// Parses the masks of the channels out of the chunk section.
+pub(crate) fn decode_masks(chunks: Chunks<'_>, descriptor: &DTM) -> Result<Masks, DecodeError> {
+    let mut masks = Masks::default();
+
+    for chunk in chunks {
+        let (id, data) = chunk?;
+
+        if id == ChunkId::MASK {
+            let (channel, mask) = ChannelMask::decode(data, descriptor.pixel_count())?;
+
+            if channel >= descriptor.channel_count as usize {
+                return Err(DecodeError::InvalidChunk(ChunkId::MASK));
+            }
+
+            masks[channel] = Some(mask);
+        }
+    }
+
+    Ok(masks)
+}
+
+fn encode_runs(valid: &[bool]) -> Vec<u8> {
+    let mut runs = Vec::new();
+    let mut state = true;
+    let mut length = 0u64;
+
+    for &pixel in valid {
+        if pixel != state {
+            encode_varint(length, &mut runs);
+            state = pixel;
+            length = 0;
+        }
+
+        length += 1;
+    }
+
+    encode_varint(length, &mut runs);
+
+    runs
+}
+
+fn decode_runs(mut runs: &[u8], pixel_count: usize) -> Option<Vec<bool>> {
+    let mut valid = Vec::new();
+    let mut state = true;
+
+    while !runs.is_empty() {
+        let length = decode_varint(&mut runs)?;
+
+        if length > (pixel_count - valid.len()) as u64 {
+            return None;
+        }
+
+        valid.resize(valid.len() + length as usize, state);
+        state = !state;
+    }
+
+    (valid.len() == pixel_count).then_some(valid)
+}
+
+fn encode_varint(mut value: u64, data: &mut Vec<u8>) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    data.push(value as u8);
+}
+
+fn decode_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = data.split_first()?;
+        *data = rest;
+
+        value |= ((byte & 0x7F) as u64).checked_shl(shift)?;
+
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        testing::{assert_decodes, descriptor, terrain},
+        SampleFormat,
+    };
+
+    #[test]
+    fn roundtrips_bitmaps_and_runs() {
+        let scattered: Vec<bool> = (0..1000).map(|index| index % 3 != 0).collect();
+        let blocks: Vec<bool> = (0..1000).map(|index| index / 300 != 1).collect();
+
+        for (valid, kind) in [(scattered, BITMAP), (blocks, RUNS)] {
+            let mask = ChannelMask {
+                valid,
+                nodata: Some(7),
+            };
+            let chunk = mask.encode(2);
+            let masked = mask.valid.iter().filter(|&&valid| !valid).count();
+
+            assert_eq!(chunk.data[6], kind);
+            assert_eq!(ChannelMask::decode(&chunk.data, 1000).unwrap(), (2, mask));
+            assert_eq!(
+                ChannelMask::masked_count(&chunk.data, 1000).unwrap(),
+                (2, masked)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_runs_not_covering_the_channel() {
+        let mask = ChannelMask {
+            valid: (0..100).map(|index| index < 50).collect(),
+            nodata: None,
+        };
+        let chunk = mask.encode(0);
+
+        for pixel_count in [99, 101] {
+            assert!(ChannelMask::decode(&chunk.data, pixel_count).is_err());
+            assert!(ChannelMask::masked_count(&chunk.data, pixel_count).is_err());
+        }
+    }
+
+    #[test]
+    fn roundtrips_masked_images() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (4, SampleFormat::Unsigned),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in 1..=3 {
+                let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                let (pixel_size, channel_count) = (pixel_size as usize, channel_count as usize);
+                let decoded = terrain(&descriptor);
+
+                // The first channel is masked explicitly, the last one by its nodata value,
+                // which is the value of its flat area.
+                let valid: Vec<bool> = (0..descriptor.pixel_count())
+                    .map(|index| (index / 37 + index % 37 * 2) % 11 > 2)
+                    .collect();
+                let last = channel_count - 1;
+                let start = last * pixel_size;
+                let mut nodata = [0; 4];
+                nodata[..pixel_size].copy_from_slice(&decoded[start..start + pixel_size]);
+                let nodata = u32::from_le_bytes(nodata);
+
+                let mut expected = decoded.clone();
+
+                for (texel, &valid) in expected
+                    .chunks_exact_mut(channel_count * pixel_size)
+                    .zip(&valid)
+                {
+                    if !valid {
+                        texel[..pixel_size].fill(0);
+
+                        if last == 0 {
+                            texel[..pixel_size]
+                                .copy_from_slice(&nodata.to_le_bytes()[..pixel_size]);
+                        }
+                    }
+                }
+
+                let encoder = Encoder::new(descriptor)
+                    .validity_mask(0, valid.clone())
+                    .nodata(last, nodata);
+                let encoded = encoder.encode_alloc(&decoded).unwrap();
+                assert_decodes(&encoded, &expected);
+
+                let (_, _, masks) = DTM::decode_alloc_with_masks(&encoded).unwrap();
+                assert_eq!(masks[0].as_ref(), Some(&valid));
+                assert!(masks[last].as_ref().unwrap().iter().any(|&valid| !valid));
+            }
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Typed metadata, which is stored in chunks of the chunk section.
+
+use crate::{
+    chunk::{Chunk, ChunkId, Chunks},
+    decode::DecodeError,
+    mask::ChannelMask,
+};
+use std::{fs, io, path::Path};
+
+impl ChunkId {
+    /// The [`GeoTransform`] of the image, stored as six f64 in the order used by GDAL.
+    pub const GEO_TRANSFORM: ChunkId = ChunkId(*b"gTRN");
+    /// The coordinate reference system of the image,
+    /// stored as a kind byte (0 = EPSG, 1 = WKT) followed by a u32 or UTF-8 text.
+    pub const CRS: ChunkId = ChunkId(*b"gCRS");
+    /// The [`Scale`] of a channel, stored as the channel (u8), the scale (f64),
+    /// the offset (f64) and the UTF-8 unit.
+    pub const SCALE: ChunkId = ChunkId(*b"sCAL");
+}
+
+/// The metadata of a DTM image.
+///
+/// Chunks, which are not represented by a typed field, are kept in `chunks`.
+/// This includes a CRS chunk without a transform, as a [`Georeference`] requires both.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub georeference: Option<Georeference>,
+    /// The scale of each channel, if it is stored.
+    pub scales: [Option<Scale>; 4],
+    /// The bits of the pixel value marking the masked pixels of each channel, if it is stored.
+    pub nodata: [Option<u32>; 4],
+    pub chunks: Vec<Chunk>,
+}
+
+impl Metadata {
+    /// Parses the typed metadata out of the chunk section.
+    pub(crate) fn decode(chunks: Chunks<'_>) -> Result<Self, DecodeError> {
+        let mut metadata = Metadata::default();
+        let mut transform = None;
+        let mut crs = None;
+
+        for chunk in chunks {
+            let (id, data) = chunk?;
+
+            match id {
+                ChunkId::GEO_TRANSFORM => transform = Some(GeoTransform::decode(data)?),
+                ChunkId::CRS => crs = Some((Crs::decode(data)?, data)),
+                ChunkId::SCALE => {
+                    let (channel, scale) = Scale::decode(data)?;
+                    metadata.scales[channel] = Some(scale);
+                }
+                ChunkId::MASK => {
+                    let (channel, nodata) = ChannelMask::decode_header(data)?;
+                    metadata.nodata[channel] = nodata;
+                }
+                _ => metadata.chunks.push(Chunk::new(id, data.to_vec())),
+            }
+        }
+
+        match (transform, crs) {
+            (Some(transform), crs) => {
+                metadata.georeference = Some(Georeference {
+                    transform,
+                    crs: crs.map(|(crs, _)| crs),
+                });
+            }
+            (None, Some((_, data))) => metadata
+                .chunks
+                .push(Chunk::new(ChunkId::CRS, data.to_vec())),
+            (None, None) => {}
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// The mapping of the stored pixel values of a channel to physical values,
+/// i.e. `physical = stored * scale + offset`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub scale: f64,
+    pub offset: f64,
+    /// The unit of the physical values, e.g. `"m"`.
+    pub unit: String,
+}
+
+impl Default for Scale {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            offset: 0.0,
+            unit: String::new(),
+        }
+    }
+}
+
+impl Scale {
+    /// Returns the chunk storing the scale of the channel.
+    pub(crate) fn encode(&self, channel: usize) -> Chunk {
+        let mut data = vec![channel as u8];
+        data.extend_from_slice(&self.scale.to_be_bytes());
+        data.extend_from_slice(&self.offset.to_be_bytes());
+        data.extend_from_slice(self.unit.as_bytes());
+
+        Chunk::new(ChunkId::SCALE, data)
+    }
+
+    fn decode(data: &[u8]) -> Result<(usize, Self), DecodeError> {
+        let invalid = DecodeError::InvalidChunk(ChunkId::SCALE);
+
+        if data.len() < 17 || data[0] >= 4 {
+            return Err(invalid);
+        }
+
+        let scale = f64::from_be_bytes(data[1..9].try_into().unwrap());
+        let offset = f64::from_be_bytes(data[9..17].try_into().unwrap());
+        let unit = match std::str::from_utf8(&data[17..]) {
+            Ok(unit) => unit.to_string(),
+            Err(_) => return Err(invalid),
+        };
+
+        Ok((
+            data[0] as usize,
+            Self {
+                scale,
+                offset,
+                unit,
+            },
+        ))
+    }
+}
+
+/// The placement of an image in a coordinate reference system.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Georeference {
+    pub transform: GeoTransform,
+    pub crs: Option<Crs>,
+}
+
+impl Georeference {
+    /// Returns the chunks storing the georeference.
+    pub(crate) fn encode(&self) -> Vec<Chunk> {
+        let mut chunks = vec![self.transform.encode()];
+        chunks.extend(self.crs.as_ref().map(Crs::encode));
+        chunks
+    }
+
+    /// Writes a world file (`.tfw`) and, if there is a CRS, a `.prj` file next to the exported image at `path`.
+    ///
+    /// EPSG codes are converted by [`Crs::to_wkt`], which supports WGS 84 (4326), ETRS89 (4258),
+    /// Web Mercator (3857) and the UTM zones of WGS 84 (32601–32660, 32701–32760)
+    /// and ETRS89 (25828–25838). If the code is not one of these, an error of kind [`io::ErrorKind::Unsupported`] is returned before any file is written,
+    /// as GIS tools would misplace the image without its `.prj` file.
+    pub fn write_sidecars<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+
+        let wkt = match &self.crs {
+            Some(crs) => Some(crs.to_wkt().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!(
+                        "The WKT of the CRS {crs:?} is unknown, so no .prj file can be written"
+                    ),
+                )
+            })?),
+            None => None,
+        };
+
+        fs::write(path.with_extension("tfw"), self.transform.world_file())?;
+
+        if let Some(wkt) = wkt {
+            fs::write(path.with_extension("prj"), wkt)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The affine transformation from pixel to world coordinates.
+///
+/// The world coordinates of the upper left corner of the pixel at `(x, y)` are
+/// `origin_x + x * pixel_width + y * row_rotation` and
+/// `origin_y + x * column_rotation + y * pixel_height`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GeoTransform {
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub pixel_width: f64,
+    /// The height of a pixel, which is negative for north-up images.
+    pub pixel_height: f64,
+    pub row_rotation: f64,
+    pub column_rotation: f64,
+}
+
+impl GeoTransform {
+    /// Creates the transform from the six coefficients used by GDAL.
+    pub fn from_gdal(coefficients: [f64; 6]) -> Self {
+        let [origin_x, pixel_width, row_rotation, origin_y, column_rotation, pixel_height] =
+            coefficients;
+
+        Self {
+            origin_x,
+            origin_y,
+            pixel_width,
+            pixel_height,
+            row_rotation,
+            column_rotation,
+        }
+    }
+
+    /// Returns the six coefficients used by GDAL.
+    pub fn to_gdal(&self) -> [f64; 6] {
+        [
+            self.origin_x,
+            self.pixel_width,
+            self.row_rotation,
+            self.origin_y,
+            self.column_rotation,
+            self.pixel_height,
+        ]
+    }
+
+    /// Returns the contents of an ESRI world file.
+    ///
+    /// In contrast to the transform, world files reference the center of the upper left pixel.
+    pub fn world_file(&self) -> String {
+        let center_x = self.origin_x + 0.5 * self.pixel_width + 0.5 * self.row_rotation;
+        let center_y = self.origin_y + 0.5 * self.column_rotation + 0.5 * self.pixel_height;
+
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n",
+            self.pixel_width,
+            self.column_rotation,
+            self.row_rotation,
+            self.pixel_height,
+            center_x,
+            center_y
+        )
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = self
+            .to_gdal()
+            .iter()
+            .flat_map(|coefficient| coefficient.to_be_bytes())
+            .collect();
+
+        Chunk::new(ChunkId::GEO_TRANSFORM, data)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if data.len() != 48 {
+            return Err(DecodeError::InvalidChunk(ChunkId::GEO_TRANSFORM));
+        }
+
+        let mut coefficients = [0.0; 6];
+
+        for (coefficient, bytes) in coefficients.iter_mut().zip(data.chunks_exact(8)) {
+            *coefficient = f64::from_be_bytes(bytes.try_into().unwrap());
+        }
+
+        Ok(Self::from_gdal(coefficients))
+    }
+}
+
+/// A coordinate reference system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Crs {
+    /// The code of the CRS in the EPSG registry.
+    Epsg(u32),
+    /// The well-known text representation of the CRS.
+    Wkt(String),
+}
+
+impl Crs {
+    /// Returns the well-known text of the CRS in the ESRI dialect used by `.prj` files.
+    ///
+    /// EPSG codes are only supported for WGS 84 (4326), ETRS89 (4258), Web Mercator (3857)
+    /// and the UTM zones of WGS 84 (32601–32660, 32701–32760) and ETRS89 (25828–25838).
+    pub fn to_wkt(&self) -> Option<String> {
+        const WGS_84: &str = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+        const ETRS_89: &str = r#"GEOGCS["GCS_ETRS_1989",DATUM["D_ETRS_1989",SPHEROID["GRS_1980",6378137.0,298.257222101]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+
+        let utm = |name: &str, geographic: &str, zone: u32, north: bool| {
+            format!(
+                r#"PROJCS["{name}_UTM_Zone_{zone}{}",{geographic},PROJECTION["Transverse_Mercator"],PARAMETER["False_Easting",500000.0],PARAMETER["False_Northing",{}],PARAMETER["Central_Meridian",{}.0],PARAMETER["Scale_Factor",0.9996],PARAMETER["Latitude_Of_Origin",0.0],UNIT["Meter",1.0]]"#,
+                if north { "N" } else { "S" },
+                if north { "0.0" } else { "10000000.0" },
+                zone as i32 * 6 - 183,
+            )
+        };
+
+        match *self {
+            Crs::Wkt(ref wkt) => Some(wkt.clone()),
+            Crs::Epsg(4326) => Some(WGS_84.to_string()),
+            Crs::Epsg(4258) => Some(ETRS_89.to_string()),
+            Crs::Epsg(3857) => Some(format!(
+                r#"PROJCS["WGS_1984_Web_Mercator_Auxiliary_Sphere",{WGS_84},PROJECTION["Mercator_Auxiliary_Sphere"],PARAMETER["False_Easting",0.0],PARAMETER["False_Northing",0.0],PARAMETER["Central_Meridian",0.0],PARAMETER["Standard_Parallel_1",0.0],PARAMETER["Auxiliary_Sphere_Type",0.0],UNIT["Meter",1.0]]"#
+            )),
+            Crs::Epsg(code @ 32601..=32660) => Some(utm("WGS_1984", WGS_84, code - 32600, true)),
+            Crs::Epsg(code @ 32701..=32760) => Some(utm("WGS_1984", WGS_84, code - 32700, false)),
+            Crs::Epsg(code @ 25828..=25838) => Some(utm("ETRS_1989", ETRS_89, code - 25800, true)),
+            Crs::Epsg(_) => None,
+        }
+    }
+
+    fn encode(&self) -> Chunk {
+        let data = match self {
+            Crs::Epsg(code) => [&[0][..], &code.to_be_bytes()].concat(),
+            Crs::Wkt(wkt) => [&[1][..], wkt.as_bytes()].concat(),
+        };
+
+        Chunk::new(ChunkId::CRS, data)
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        match data {
+            [0, code @ ..] if code.len() == 4 => {
+                Ok(Crs::Epsg(u32::from_be_bytes(code.try_into().unwrap())))
+            }
+            [1, wkt @ ..] => match std::str::from_utf8(wkt) {
+                Ok(wkt) => Ok(Crs::Wkt(wkt.to_string())),
+                Err(_) => Err(DecodeError::InvalidChunk(ChunkId::CRS)),
+            },
+            _ => Err(DecodeError::InvalidChunk(ChunkId::CRS)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        reader::DtmReader,
+        testing::{descriptor, terrain},
+        SampleFormat, DTM,
+    };
+
+    fn georeference(crs: Option<Crs>) -> Georeference {
+        Georeference {
+            transform: GeoTransform::from_gdal([500000.0, 1.0, 0.0, 5400000.0, 0.0, -1.0]),
+            crs,
+        }
+    }
+
+    #[test]
+    fn converts_epsg_codes_to_wkt() {
+        let utm = Crs::Epsg(32633).to_wkt().unwrap();
+        assert!(utm.starts_with(r#"PROJCS["WGS_1984_UTM_Zone_33N",GEOGCS["GCS_WGS_1984""#));
+        assert!(utm.contains(r#"PARAMETER["Central_Meridian",15.0]"#));
+
+        let south = Crs::Epsg(32701).to_wkt().unwrap();
+        assert!(south.contains(r#"PARAMETER["False_Northing",10000000.0]"#));
+        assert!(south.contains(r#"PARAMETER["Central_Meridian",-177.0]"#));
+
+        assert!(Crs::Epsg(25832)
+            .to_wkt()
+            .unwrap()
+            .contains("ETRS_1989_UTM_Zone_32N"));
+        assert!(Crs::Epsg(4326).to_wkt().unwrap().starts_with("GEOGCS"));
+        assert!(Crs::Epsg(3857)
+            .to_wkt()
+            .unwrap()
+            .contains("Mercator_Auxiliary_Sphere"));
+        assert_eq!(Crs::Wkt("LOCAL_CS".into()).to_wkt().unwrap(), "LOCAL_CS");
+        assert_eq!(Crs::Epsg(32600).to_wkt(), None);
+        assert_eq!(Crs::Epsg(2056).to_wkt(), None);
+    }
+
+    #[test]
+    fn roundtrips_georeferences() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let decoded = terrain(&descriptor);
+
+        for crs in [
+            None,
+            Some(Crs::Epsg(25832)),
+            Some(Crs::Wkt("LOCAL_CS[\"local\"]".into())),
+        ] {
+            let encoded = Encoder::new(descriptor)
+                .georeference(georeference(crs.clone()))
+                .encode_alloc(&decoded)
+                .unwrap();
+
+            let (_, metadata) = DTM::decode_metadata(&encoded).unwrap();
+            assert_eq!(metadata.georeference, Some(georeference(crs)));
+            assert!(metadata.chunks.is_empty());
+
+            let reader = DtmReader::new(encoded.as_slice()).unwrap();
+            assert_eq!(reader.metadata(), &metadata);
+        }
+    }
+
+    #[test]
+    fn keeps_crs_without_transform() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let crs = Crs::Epsg(32633).encode();
+        let encoded = Encoder::new(descriptor)
+            .chunk(crs.clone())
+            .encode_alloc(&terrain(&descriptor))
+            .unwrap();
+
+        let (_, metadata) = DTM::decode_metadata(&encoded).unwrap();
+        assert_eq!(metadata.georeference, None);
+        assert_eq!(metadata.chunks, [crs]);
+        assert_eq!(
+            DtmReader::new(encoded.as_slice()).unwrap().metadata(),
+            &metadata
+        );
+    }
+
+    #[test]
+    fn writes_sidecars() {
+        let directory = std::env::temp_dir().join(format!("dtm-sidecars-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("tile.dtm");
+
+        georeference(Some(Crs::Epsg(32633)))
+            .write_sidecars(&path)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(path.with_extension("tfw")).unwrap(),
+            "1\n0\n0\n-1\n500000.5\n5399999.5\n"
+        );
+        assert_eq!(
+            fs::read_to_string(path.with_extension("prj")).unwrap(),
+            Crs::Epsg(32633).to_wkt().unwrap()
+        );
+
+        let unsupported = directory.join("unsupported.dtm");
+        let error = georeference(Some(Crs::Epsg(2056)))
+            .write_sidecars(&unsupported)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(!unsupported.with_extension("tfw").exists());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+/// A pixel of one of the supported bit depths.
+///
+/// The encoder and decoder are generic over this trait,
+/// so that a dedicated loop is generated for every bit depth.
+pub(crate) trait Pixel: Copy + Default + Eq {
+    /// The size of a pixel in bytes.
+    const SIZE: usize;
+
+    /// Whether the opcode table contains the wide diff, which takes the place of the longest run.
+    const WIDE_DIFF: bool = false;
+
+    /// The longest run, that can be encoded by a single opcode.
+    const MAX_RUN_LENGTH: u8 = if Self::WIDE_DIFF { 62 } else { 63 };
+
+    /// Reads a pixel from its little-endian bytes.
+    fn read(bytes: &[u8]) -> Self;
+
+    /// Writes the pixel as little-endian bytes.
+    fn write(self, bytes: &mut [u8]);
+
+    /// Returns the index of the pixel inside the pixel cache.
+    fn cache_index(self) -> usize;
+
+    /// Returns the difference between the pixel and its prediction.
+    ///
+    /// The decoder adds the difference using wrapping arithmetic,
+    /// so it may be taken modulo the value range of the pixel.
+    fn diff(self, prediction: Self) -> i32;
+
+    /// Adds the difference to the prediction using wrapping arithmetic.
+    fn add_diff(self, diff: i32) -> Self;
+
+    /// Selects the neighbour closest to the linear estimate `previous + above - diagonal`.
+    fn paeth(previous: Self, above: Self, diagonal: Self) -> Self;
+
+    /// Returns the mean of the two pixels rounded down.
+    fn average(previous: Self, above: Self) -> Self;
+
+    /// The median edge detector of LOCO-I, which clamps the linear estimate
+    /// `previous + above - diagonal` to the range between `previous` and `above`.
+    fn med(previous: Self, above: Self, diagonal: Self) -> Self;
+
+    /// Returns the value of the pixel according to its sample format.
+    fn value(self) -> f64;
+}
+
+macro_rules! impl_pixel {
+    ($pixel:ty, wide_diff: $wide_diff:expr, diff: |$a:ident, $b:ident| $diff:expr) => {
+        impl Pixel for $pixel {
+            const SIZE: usize = std::mem::size_of::<$pixel>();
+            const WIDE_DIFF: bool = $wide_diff;
+
+            #[inline]
+            fn read(bytes: &[u8]) -> Self {
+                Self::from_le_bytes(bytes[..Self::SIZE].try_into().unwrap())
+            }
+
+            #[inline]
+            fn write(self, bytes: &mut [u8]) {
+                bytes[..Self::SIZE].copy_from_slice(&self.to_le_bytes());
+            }
+
+            #[inline]
+            fn cache_index(self) -> usize {
+                (self % 64) as usize
+            }
+
+            #[inline]
+            fn diff(self, prediction: Self) -> i32 {
+                let ($a, $b) = (self, prediction);
+                $diff
+            }
+
+            #[inline]
+            fn add_diff(self, diff: i32) -> Self {
+                self.wrapping_add(diff as Self)
+            }
+
+            #[inline]
+            fn paeth(previous: Self, above: Self, diagonal: Self) -> Self {
+                let p = previous.wrapping_add(above).wrapping_sub(diagonal);
+
+                let diff_p_previous = p.abs_diff(previous);
+                let diff_p_above = p.abs_diff(above);
+                let diff_p_diagonal = p.abs_diff(diagonal);
+
+                if diff_p_previous <= diff_p_above && diff_p_previous <= diff_p_diagonal {
+                    previous
+                } else if diff_p_above <= diff_p_diagonal {
+                    above
+                } else {
+                    diagonal
+                }
+            }
+
+            #[inline]
+            fn average(previous: Self, above: Self) -> Self {
+                (previous & above) + ((previous ^ above) >> 1)
+            }
+
+            #[inline]
+            fn med(previous: Self, above: Self, diagonal: Self) -> Self {
+                let (min, max) = (previous.min(above), previous.max(above));
+
+                if diagonal >= max {
+                    min
+                } else if diagonal <= min {
+                    max
+                } else {
+                    // The estimate lies between the two neighbours, so the wrapping cancels out.
+                    previous.wrapping_add(above).wrapping_sub(diagonal)
+                }
+            }
+
+            #[inline]
+            fn value(self) -> f64 {
+                self as f64
+            }
+        }
+    };
+}
+
+// 8 bit values wrap around, so that the single diff covers a quarter of the value range in both directions.
+impl_pixel!(u8, wide_diff: false, diff: |pixel, prediction| pixel.wrapping_sub(prediction) as i8 as i32);
+impl_pixel!(u16, wide_diff: false, diff: |pixel, prediction| pixel as i32 - prediction as i32);
+// 32 bit differences do not fit into an i32, thus they wrap around as well.
+// Neighbouring values are further apart than in 8 or 16 bit images, so the wide diff is used for them.
+impl_pixel!(u32, wide_diff: true, diff: |pixel, prediction| pixel.wrapping_sub(prediction) as i32);
+
+/// A 32 bit float, which is stored as an unsigned key that preserves the order of the floats.
+///
+/// Positive floats get their sign bit set, while all bits of negative floats are flipped.
+/// This turns neighbouring floats into neighbouring keys, so that the 32 bit codec applies unchanged.
+/// The mapping is a bijection of the bit patterns, thus every float round trips bit-exactly.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Float(u32);
+
+impl Pixel for Float {
+    const SIZE: usize = 4;
+    const WIDE_DIFF: bool = true;
+
+    #[inline]
+    fn read(bytes: &[u8]) -> Self {
+        let bits = u32::read(bytes);
+
+        Float(if bits >> 31 == 1 {
+            !bits
+        } else {
+            bits | 1 << 31
+        })
+    }
+
+    #[inline]
+    fn write(self, bytes: &mut [u8]) {
+        self.bits().write(bytes);
+    }
+
+    #[inline]
+    fn cache_index(self) -> usize {
+        self.0.cache_index()
+    }
+
+    #[inline]
+    fn diff(self, prediction: Self) -> i32 {
+        self.0.diff(prediction.0)
+    }
+
+    #[inline]
+    fn add_diff(self, diff: i32) -> Self {
+        Float(self.0.add_diff(diff))
+    }
+
+    #[inline]
+    fn paeth(previous: Self, above: Self, diagonal: Self) -> Self {
+        Float(u32::paeth(previous.0, above.0, diagonal.0))
+    }
+
+    #[inline]
+    fn average(previous: Self, above: Self) -> Self {
+        Float(u32::average(previous.0, above.0))
+    }
+
+    #[inline]
+    fn med(previous: Self, above: Self, diagonal: Self) -> Self {
+        Float(u32::med(previous.0, above.0, diagonal.0))
+    }
+
+    #[inline]
+    fn value(self) -> f64 {
+        f32::from_bits(self.bits()) as f64
+    }
+}
+
+impl Float {
+    /// Returns the bits of the float.
+    #[inline]
+    fn bits(self) -> u32 {
+        let key = self.0;
+
+        if key >> 31 == 1 {
+            key & !(1 << 31)
+        } else {
+            !key
+        }
+    }
+}
+
+/// A signed integer, which is stored as an unsigned key by flipping its sign bit.
+///
+/// This maps the most negative value to zero and keeps neighbouring values adjacent across zero,
+/// so that the paeth filter and the diffs behave like they do for unsigned pixels.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Signed<P>(P);
+
+macro_rules! impl_signed {
+    ($pixel:ty, $signed:ty) => {
+        impl Pixel for Signed<$pixel> {
+            const SIZE: usize = <$pixel>::SIZE;
+            const WIDE_DIFF: bool = <$pixel>::WIDE_DIFF;
+
+            #[inline]
+            fn read(bytes: &[u8]) -> Self {
+                Signed(<$pixel>::read(bytes) ^ 1 << (<$pixel>::BITS - 1))
+            }
+
+            #[inline]
+            fn write(self, bytes: &mut [u8]) {
+                (self.0 ^ 1 << (<$pixel>::BITS - 1)).write(bytes);
+            }
+
+            #[inline]
+            fn cache_index(self) -> usize {
+                self.0.cache_index()
+            }
+
+            #[inline]
+            fn diff(self, prediction: Self) -> i32 {
+                self.0.diff(prediction.0)
+            }
+
+            #[inline]
+            fn add_diff(self, diff: i32) -> Self {
+                Signed(self.0.add_diff(diff))
+            }
+
+            #[inline]
+            fn paeth(previous: Self, above: Self, diagonal: Self) -> Self {
+                Signed(<$pixel>::paeth(previous.0, above.0, diagonal.0))
+            }
+
+            #[inline]
+            fn average(previous: Self, above: Self) -> Self {
+                Signed(<$pixel>::average(previous.0, above.0))
+            }
+
+            #[inline]
+            fn med(previous: Self, above: Self, diagonal: Self) -> Self {
+                Signed(<$pixel>::med(previous.0, above.0, diagonal.0))
+            }
+
+            #[inline]
+            fn value(self) -> f64 {
+                (self.0 ^ 1 << (<$pixel>::BITS - 1)) as $signed as f64
+            }
+        }
+    };
+}
+
+impl_signed!(u8, i8);
+impl_signed!(u16, i16);
+impl_signed!(u32, i32);
+
+/// An element of a slice of decoded pixels, which is either a byte of the little-endian pixels
+/// or a whole pixel of a typed slice.
+pub(crate) trait Element<P: Pixel>: Copy + Default + Send + Sync {
+    /// The number of elements a pixel takes up.
+    const PER_PIXEL: usize;
+
+    /// Reads the pixel at `index` from the elements.
+    fn get(data: &[Self], index: usize) -> P;
+
+    /// Writes the pixel at `index` into the elements.
+    fn set(data: &mut [Self], index: usize, pixel: P);
+}
+
+impl<P: Pixel> Element<P> for u8 {
+    const PER_PIXEL: usize = P::SIZE;
+
+    #[inline]
+    fn get(data: &[Self], index: usize) -> P {
+        P::read(&data[index * P::SIZE..])
+    }
+
+    #[inline]
+    fn set(data: &mut [Self], index: usize, pixel: P) {
+        pixel.write(&mut data[index * P::SIZE..]);
+    }
+}
+
+impl Element<u16> for u16 {
+    const PER_PIXEL: usize = 1;
+
+    #[inline]
+    fn get(data: &[Self], index: usize) -> u16 {
+        data[index]
+    }
+
+    #[inline]
+    fn set(data: &mut [Self], index: usize, pixel: u16) {
+        data[index] = pixel;
+    }
+}
+
+impl Element<Signed<u16>> for i16 {
+    const PER_PIXEL: usize = 1;
+
+    #[inline]
+    fn get(data: &[Self], index: usize) -> Signed<u16> {
+        Signed(data[index] as u16 ^ 1 << 15)
+    }
+
+    #[inline]
+    fn set(data: &mut [Self], index: usize, pixel: Signed<u16>) {
+        data[index] = (pixel.0 ^ 1 << 15) as i16;
+    }
+}
+
+/// Evaluates `$body` with the type alias `$pixel` set to the pixel type of the descriptor.
+///
+/// The pixel size and sample format of the descriptor have to be validated beforehand.
+macro_rules! with_pixel {
+    ($descriptor:expr, $pixel:ident => $body:expr) => {
+        match ($descriptor.sample_format, $descriptor.pixel_size) {
+            ($crate::SampleFormat::Unsigned, 1) => {
+                type $pixel = u8;
+                $body
+            }
+            ($crate::SampleFormat::Unsigned, 2) => {
+                type $pixel = u16;
+                $body
+            }
+            ($crate::SampleFormat::Unsigned, _) => {
+                type $pixel = u32;
+                $body
+            }
+            ($crate::SampleFormat::Signed, 1) => {
+                type $pixel = $crate::pixel::Signed<u8>;
+                $body
+            }
+            ($crate::SampleFormat::Signed, 2) => {
+                type $pixel = $crate::pixel::Signed<u16>;
+                $body
+            }
+            ($crate::SampleFormat::Signed, _) => {
+                type $pixel = $crate::pixel::Signed<u32>;
+                $body
+            }
+            ($crate::SampleFormat::Float, _) => {
+                type $pixel = $crate::pixel::Float;
+                $body
+            }
+        }
+    };
+}
+
+pub(crate) use with_pixel;
@@ -0,0 +1,284 @@
+//! The predictors, which the encoder selects per row when adaptive prediction is enabled.
+//!
+//! The predictor of each row is stored as a byte in front of the opcodes of the channel or tile.
+//! Pixels of the first row are always predicted from their left neighbour
+//! and pixels of the first column from the pixel above.
+//!
+//! With inter-channel prediction, the channels store the residuals to the preceding channel,
+//! which are predicted like pixels.
+
+use crate::pixel::Pixel;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Predictor {
+    Left = 0,
+    Up = 1,
+    Average = 2,
+    Paeth = 3,
+    /// The median edge detector of LOCO-I.
+    Med = 4,
+}
+
+impl Predictor {
+    const ALL: [Predictor; 5] = [
+        Predictor::Left,
+        Predictor::Up,
+        Predictor::Average,
+        Predictor::Paeth,
+        Predictor::Med,
+    ];
+
+    #[inline]
+    fn predict<P: Pixel>(self, previous: P, above: P, diagonal: P) -> P {
+        match self {
+            Predictor::Left => previous,
+            Predictor::Up => above,
+            Predictor::Average => P::average(previous, above),
+            Predictor::Paeth => P::paeth(previous, above, diagonal),
+            Predictor::Med => P::med(previous, above, diagonal),
+        }
+    }
+
+    /// Parses the predictors of the rows, or returns the position of the first invalid one.
+    pub(crate) fn decode_table(table: &[u8]) -> Result<Vec<Self>, usize> {
+        table
+            .iter()
+            .enumerate()
+            .map(|(row, &id)| Self::ALL.get(id as usize).copied().ok_or(row))
+            .collect()
+    }
+}
+
+/// Predicts the pixel at `index` of a channel of the given width
+/// from its neighbours, which are returned by `get`.
+#[inline]
+pub(crate) fn predict<P: Pixel>(
+    predictors: &[Predictor],
+    index: usize,
+    width: usize,
+    get: impl Fn(usize) -> P,
+) -> P {
+    if index < width {
+        return if index == 0 {
+            P::default()
+        } else {
+            get(index - 1)
+        };
+    }
+
+    let above = get(index - width);
+
+    if index.is_multiple_of(width) {
+        above
+    } else {
+        predictors[index / width].predict(get(index - 1), above, get(index - width - 1))
+    }
+}
+
+/// Returns the residual of a pixel to the pixel of the preceding channel,
+/// which is stored instead of the pixel with inter-channel prediction.
+#[inline]
+pub(crate) fn channel_residual<P: Pixel>(pixel: P, reference: P) -> P {
+    P::default().add_diff(pixel.diff(reference))
+}
+
+/// Restores a pixel from its residual to the pixel of the preceding channel.
+#[inline]
+pub(crate) fn restore_channel<P: Pixel>(residual: P, reference: P) -> P {
+    reference.add_diff(residual.diff(P::default()))
+}
+
+/// Selects the predictor of every row, which minimises the total bit length of the differences
+/// to the valid pixels of the row.
+pub(crate) fn select<P: Pixel>(
+    width: usize,
+    height: usize,
+    get: impl Fn(usize) -> P,
+    is_valid: impl Fn(usize) -> bool,
+) -> Vec<Predictor> {
+    let mut predictors = vec![Predictor::Left; height];
+
+    for (y, predictor) in predictors.iter_mut().enumerate().skip(1) {
+        let mut costs = [0u64; Predictor::ALL.len()];
+
+        for index in y * width + 1..(y + 1) * width {
+            if !is_valid(index) {
+                continue;
+            }
+
+            let (pixel, previous) = (get(index), get(index - 1));
+            let (above, diagonal) = (get(index - width), get(index - width - 1));
+
+            for (cost, predictor) in costs.iter_mut().zip(Predictor::ALL) {
+                let diff = pixel.diff(predictor.predict(previous, above, diagonal));
+                *cost += (u32::BITS - diff.unsigned_abs().leading_zeros()) as u64;
+            }
+        }
+
+        let best = (0..costs.len()).min_by_key(|&index| costs[index]).unwrap();
+        *predictor = Predictor::ALL[best];
+    }
+
+    predictors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        decode::DecodeError,
+        encode::Encoder,
+        info::DtmInfo,
+        reader::DtmReader,
+        testing::{assert_decodes, descriptor, features, roundtrip, terrain},
+        SampleFormat, DTM, FEATURE_INTER_CHANNEL, FEATURE_PREDICTORS, FEATURE_TILES,
+    };
+
+    #[test]
+    fn decodes_tables() {
+        let predictors = Predictor::decode_table(&[0, 1, 2, 3, 4]).unwrap();
+
+        assert_eq!(predictors, Predictor::ALL);
+        assert_eq!(Predictor::decode_table(&[4, 0, 5, 1]), Err(2));
+    }
+
+    #[test]
+    fn selects_predictor_of_least_cost() {
+        // Vertical stripes are predicted exactly from the pixel above.
+        let (width, height) = (16, 4);
+        let stripes = |index: usize| (index % width * 37 % 97) as u16;
+        let predictors = select(width, height, stripes, |_| true);
+
+        assert_eq!(
+            predictors,
+            [Predictor::Left, Predictor::Up, Predictor::Up, Predictor::Up]
+        );
+
+        // Rows of a constant value are predicted exactly from the left neighbour.
+        let rows = |index: usize| (index / width * 1000) as u16;
+        let predictors = select(width, height, rows, |_| true);
+
+        assert!(predictors
+            .iter()
+            .all(|&predictor| predictor == Predictor::Left));
+    }
+
+    #[test]
+    fn roundtrips_adaptive_prediction() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in [1, 2] {
+                let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                let decoded = terrain(&descriptor);
+
+                let encoder = Encoder::new(descriptor).adaptive_prediction(true);
+                let encoded = roundtrip(&encoder, &decoded);
+                assert_eq!(features(&encoded), FEATURE_PREDICTORS);
+
+                let encoded = roundtrip(&encoder.tiles(16, 8), &decoded);
+                assert_eq!(features(&encoded), FEATURE_PREDICTORS | FEATURE_TILES);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_predictors() {
+        let mut encoded = include_bytes!("../tests/data/predictors.dtm").to_vec();
+        let header_size = DtmInfo::from_slice(&encoded).unwrap().header_size;
+        encoded[header_size + 1] = Predictor::ALL.len() as u8;
+
+        assert!(matches!(
+            DTM::decode_alloc(&encoded),
+            Err(DecodeError::CorruptData { channel: 0, .. })
+        ));
+    }
+
+    fn assert_restores<P: Pixel + std::fmt::Debug>(values: &[u32]) {
+        for &pixel in values {
+            for &reference in values {
+                let pixel = P::read(&pixel.to_le_bytes());
+                let reference = P::read(&reference.to_le_bytes());
+
+                assert_eq!(
+                    restore_channel(channel_residual(pixel, reference), reference),
+                    pixel
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn restores_channel_residuals() {
+        let values = [0, 1, 2, 127, 128, 255, 256, 32767, 65535, 1 << 31, u32::MAX];
+
+        assert_restores::<u8>(&values);
+        assert_restores::<u16>(&values);
+        assert_restores::<u32>(&values);
+    }
+
+    #[test]
+    fn roundtrips_inter_channel_prediction() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (4, SampleFormat::Unsigned),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in 2..=4 {
+                let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                let decoded = terrain(&descriptor);
+
+                let encoder = Encoder::new(descriptor).inter_channel_prediction(true);
+                let encoded = roundtrip(&encoder, &decoded);
+                assert_eq!(features(&encoded), FEATURE_INTER_CHANNEL);
+                assert!(DtmInfo::from_slice(&encoded).unwrap().inter_channel);
+                assert!(DtmReader::new(&encoded[..]).unwrap().inter_channel());
+
+                roundtrip(&encoder.clone().tiles(16, 8), &decoded);
+                roundtrip(&encoder.adaptive_prediction(true), &decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrips_masked_inter_channel_prediction() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 3, 37, 29);
+        let decoded = terrain(&descriptor);
+
+        // The flat area of the first channel is masked by its nodata value,
+        // while the second channel is masked explicitly, so both references contain masked pixels.
+        let valid: Vec<bool> = (0..descriptor.pixel_count())
+            .map(|index| index % 5 != 0)
+            .collect();
+        let mut expected = decoded.clone();
+
+        for (texel, &valid) in expected.chunks_exact_mut(6).zip(&valid) {
+            if !valid {
+                texel[2..4].fill(0);
+            }
+        }
+
+        for tiles in [None, Some((16, 8))] {
+            let mut encoder = Encoder::new(descriptor)
+                .inter_channel_prediction(true)
+                .nodata(0, 500)
+                .validity_mask(1, valid.clone());
+
+            if let Some((tile_width, tile_height)) = tiles {
+                encoder = encoder.tiles(tile_width, tile_height);
+            }
+
+            let encoded = encoder.encode_alloc(&decoded).unwrap();
+            assert_decodes(&encoded, &expected);
+        }
+    }
+}
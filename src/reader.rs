@@ -0,0 +1,848 @@
+use crate::{
+    chunk::{Chunk, Chunks},
+    crc::Crc32,
+    decode::{
+        decode, decode_residuals, decode_tile, header_size, header_size_without_chunks,
+        restore_plane, try_vec, DecodeError, Decoded, Encoded, Header, Target,
+    },
+    mask::{decode_masks, ChannelMask, Masks},
+    metadata::Metadata,
+    pixel::{with_pixel, Pixel},
+    predictor::Predictor,
+    rice::ResidualDecoder,
+    tile::Tiles,
+    Coding, DTM, DTM_LEGACY_HEADER_SIZE,
+};
+use std::io::{self, Read};
+
+/// The number of encoded bytes that are read from the reader at once.
+const CHUNK_SIZE: usize = 1 << 16;
+
+/// The maximum number of pixels a single opcode may decode past the end of a row.
+const MAX_OVERSHOOT: usize = 64;
+
+/// A decoded row of a single channel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Row<'a> {
+    /// The channel the row belongs to.
+    pub channel: u32,
+    /// The index of the row inside the channel.
+    pub y: u32,
+    /// The little-endian pixel data of the row.
+    pub data: &'a [u8],
+    /// Whether each pixel of the row is valid, if the channel is masked.
+    ///
+    /// Masked pixels hold the nodata value of the channel, or zero if it has none.
+    pub valid: Option<&'a [bool]>,
+}
+
+/// A pull-based decoder, that decodes a DTM image row by row from a [`Read`] source.
+///
+/// The encoded data is read in fixed-size chunks and only the last rows are kept in memory,
+/// so neither the encoded nor the decoded image has to fit into memory at once.
+///
+/// Channels are stored one after another, thus all rows of a channel are returned
+/// before the rows of the next channel.
+/// Images with inter-channel prediction (see
+/// [`Encoder::inter_channel_prediction`](crate::encode::Encoder::inter_channel_prediction))
+/// additionally keep the rows of the preceding channel in memory, which grow to a whole channel
+/// of `width * height * pixel_size` bytes, so their memory is not bounded by the row size.
+/// [`DtmInfo::inter_channel`](crate::info::DtmInfo::inter_channel) and
+/// [`DtmReader::inter_channel`] tell these images apart before any rows are read.
+/// The encoded data of an entropy coded channel
+/// (see [`Encoder::entropy_coding`](crate::encode::Encoder::entropy_coding)) is read at once.
+///
+/// The row buffers are allocated for the width stored in the header, before any pixels are read.
+/// For untrusted input, [`DtmInfo`](crate::info::DtmInfo) can be used to reject large images first.
+pub struct DtmReader<R> {
+    input: Input<R>,
+    descriptor: DTM,
+    chunks: Vec<Chunk>,
+    metadata: Metadata,
+    masks: Masks,
+    channel_sizes: [usize; 4],
+    row: usize,
+    decoded: Box<dyn RowDecoder<R>>,
+    row_data: Vec<u8>,
+    /// The tiles of a tiled image, which are decoded one row of tiles at a time.
+    tiles: Option<TileRow>,
+    /// The coding of the compressed channels and tiles.
+    coding: Coding,
+    /// The rows of the preceding channel, if the channels store the differences to it.
+    references: Option<Vec<u8>>,
+}
+
+impl<R: Read> DtmReader<R> {
+    /// Creates a new reader and parses the header of the encoded image.
+    pub fn new(mut reader: R) -> Result<Self, DecodeError> {
+        let header = read_full_header(&mut reader)?;
+
+        let Header {
+            descriptor,
+            channel_sizes,
+            checksums,
+            tiles,
+            chunks,
+            coding,
+            inter_channel,
+            ..
+        } = DTM::decode_header(&header)?;
+
+        let metadata = Metadata::decode(Chunks::new(&header[chunks.clone()]))?;
+        let mut masks = decode_masks(Chunks::new(&header[chunks.clone()]), &descriptor)?;
+        let chunks = Chunks::new(&header[chunks]).into_vec()?;
+
+        let mut decoded: Box<dyn RowDecoder<R>> = with_pixel!(descriptor, P => {
+            Box::new(RowRing::<P>::decoded(&descriptor)?)
+        });
+
+        decoded.set_mask(masks[0].take());
+
+        // The buffers only hold a row of tiles, but the dimensions are not yet known to match
+        // the encoded data, so they are allocated fallibly.
+        let row_size = row_width(&descriptor) * descriptor.pixel_size as usize;
+
+        let tiles = match tiles {
+            Some(tiles) => {
+                let tile_size = tiles.tiling.largest_tile();
+
+                Some(TileRow {
+                    encoded: Vec::new(),
+                    tile: try_vec(0, tile_size.pixel_count() * descriptor.pixel_size as usize)?,
+                    rows: try_vec(0, tile_size.height * row_size)?,
+                    tiles,
+                    coding,
+                })
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            input: Input {
+                reader,
+                channel: 0,
+                channel_size: channel_sizes[0],
+                remaining: channel_sizes[0],
+                chunk: vec![0; CHUNK_SIZE],
+                start: 0,
+                end: 0,
+                checksums,
+                crc: Crc32::new(),
+                residuals: None,
+            },
+            descriptor,
+            chunks,
+            metadata,
+            masks,
+            channel_sizes,
+            row: 0,
+            decoded,
+            row_data: try_vec(0, row_size)?,
+            tiles,
+            coding,
+            references: (inter_channel && descriptor.channel_count > 1).then(Vec::new),
+        })
+    }
+
+    /// Returns whether the channels store the differences to the preceding channel,
+    /// which makes the reader keep up to a whole channel in memory.
+    #[inline]
+    pub fn inter_channel(&self) -> bool {
+        self.references.is_some()
+    }
+
+    /// Returns the descriptor of the encoded image.
+    #[inline]
+    pub fn descriptor(&self) -> DTM {
+        self.descriptor
+    }
+
+    /// Returns the metadata chunks of the encoded image.
+    #[inline]
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+
+    /// Returns the metadata of the encoded image.
+    #[inline]
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    /// Decodes the next row of the image.
+    ///
+    /// Returns `None` once all rows of all channels have been decoded.
+    /// Images with a width of zero have no rows to return.
+    pub fn next_row(&mut self) -> Result<Option<Row<'_>>, DecodeError> {
+        if self.descriptor.channel_count == 0 {
+            return Ok(None);
+        }
+
+        // Channels without rows are finished right after the preceding channel.
+        // Rows without pixels hold no data, so they are skipped, however many the header declares.
+        while self.row == self.descriptor.height as usize || self.descriptor.width == 0 {
+            self.input.finish_channel()?;
+
+            if self.input.channel + 1 >= self.descriptor.channel_count as usize {
+                return Ok(None);
+            }
+
+            self.next_channel();
+        }
+
+        if let Some(tiles) = &mut self.tiles {
+            let tile_height = tiles.tiles.tiling.tile_height;
+
+            if self.row.is_multiple_of(tile_height) {
+                tiles.decode(
+                    &mut self.input,
+                    self.decoded.mask(),
+                    &self.descriptor,
+                    self.row / tile_height,
+                )?;
+            }
+
+            let size = self.row_data.len();
+            let start = self.row % tile_height * size;
+            self.row_data
+                .copy_from_slice(&tiles.rows[start..start + size]);
+        } else if self.input.channel_size < self.descriptor.channel_size() {
+            if self.row == 0 && self.coding.predictors {
+                self.read_predictors()?;
+            }
+
+            if self.row == 0 && self.coding.entropy {
+                self.input.read_residuals()?;
+            }
+
+            let width = self.descriptor.width as usize;
+            let end = (self.row + 1) * width;
+
+            self.decoded
+                .decode_row(&mut self.input, end, width, &mut self.row_data)?;
+        } else {
+            self.input.read_exact(&mut self.row_data)?;
+        }
+
+        let mask = self.decoded.mask();
+        let width = self.descriptor.width as usize;
+        let pixel_size = self.descriptor.pixel_size as usize;
+        let start = self.row * width;
+
+        if let Some(mask) = mask {
+            let pixels = self.row_data.chunks_exact_mut(pixel_size);
+            mask.fill(pixels, start);
+        }
+
+        // The rows of the first channel are appended as they are decoded,
+        // so that the buffer does not grow beyond the decoded data.
+        if let Some(references) = &mut self.references {
+            if self.input.channel > 0 {
+                let references = &mut references[start * pixel_size..(start + width) * pixel_size];
+
+                with_pixel!(self.descriptor, P => {
+                    restore_plane::<P, u8>(&mut self.row_data, references, mask, start)
+                });
+
+                references.copy_from_slice(&self.row_data);
+            } else {
+                references.extend_from_slice(&self.row_data);
+            }
+        }
+
+        let row = Row {
+            channel: self.input.channel as u32,
+            y: self.row as u32,
+            data: &self.row_data,
+            valid: mask.map(|mask| &mask.valid[start..start + width]),
+        };
+
+        self.row += 1;
+
+        Ok(Some(row))
+    }
+
+    /// Decodes all remaining rows of the image and passes them to the callback `f`.
+    pub fn read_rows<F: FnMut(Row<'_>)>(&mut self, mut f: F) -> Result<(), DecodeError> {
+        while let Some(row) = self.next_row()? {
+            f(row);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the predictors of the rows, which precede the opcodes of the current channel.
+    fn read_predictors(&mut self) -> Result<(), DecodeError> {
+        let height = self.descriptor.height as usize;
+        let channel = self.input.channel;
+        let corrupt = |offset| DecodeError::CorruptData { channel, offset };
+
+        if self.input.remaining < height {
+            return Err(corrupt(self.input.channel_size));
+        }
+
+        let mut table = vec![0; height];
+        self.input.read_exact(&mut table)?;
+
+        let predictors = Predictor::decode_table(&table).map_err(corrupt)?;
+        self.decoded.set_predictors(Some(predictors));
+
+        Ok(())
+    }
+
+    fn next_channel(&mut self) {
+        self.row = 0;
+
+        self.input.channel += 1;
+        self.input.channel_size = self.channel_sizes[self.input.channel];
+        self.input.remaining = self.input.channel_size;
+        self.input.crc = Crc32::new();
+        self.input.residuals = None;
+
+        self.decoded.next_channel();
+        self.decoded.set_mask(self.masks[self.input.channel].take());
+    }
+}
+
+/// Reads the header including all of its optional sections, but none of the channel data.
+pub(crate) fn read_full_header<R: Read>(reader: &mut R) -> Result<Vec<u8>, DecodeError> {
+    read_header_sections(reader, header_size)
+}
+
+/// Reads the header up to the start of its chunks, which are left unread like the channel data.
+pub(crate) fn read_header_without_chunks<R: Read>(reader: &mut R) -> Result<Vec<u8>, DecodeError> {
+    read_header_sections(reader, header_size_without_chunks)
+}
+
+/// Reads the sections of the header, of which `header_size` returns the size.
+fn read_header_sections<R: Read>(
+    reader: &mut R,
+    header_size: fn(&[u8]) -> usize,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut header = Vec::new();
+    read_header(reader, &mut header, DTM_LEGACY_HEADER_SIZE)?;
+
+    // The size of the optional sections is only known once the preceding part has been read.
+    loop {
+        let size = header_size(&header);
+
+        if size <= header.len() {
+            return Ok(header);
+        }
+
+        read_header(reader, &mut header, size)?;
+    }
+}
+
+/// Reads the header up to the given size.
+///
+/// The buffer only grows as data arrives, so a corrupt size does not cause a huge allocation.
+fn read_header<R: Read>(
+    reader: &mut R,
+    header: &mut Vec<u8>,
+    size: usize,
+) -> Result<(), DecodeError> {
+    let missing = (size - header.len()) as u64;
+
+    if reader.by_ref().take(missing).read_to_end(header)? as u64 != missing {
+        return Err(DecodeError::InsufficientHeaderData);
+    }
+
+    Ok(())
+}
+
+/// The encoded data of the current channel, which is read in chunks.
+struct Input<R> {
+    reader: R,
+    channel: usize,
+    channel_size: usize,
+    remaining: usize,
+    chunk: Vec<u8>,
+    start: usize,
+    end: usize,
+    /// The checksums of the encoded channels, if the image stores them.
+    checksums: Option<[u32; 4]>,
+    /// The checksum of the encoded data of the current channel read so far.
+    crc: Crc32,
+    /// The Rice coded residuals of the current channel, if it is entropy coded.
+    residuals: Option<Residuals>,
+}
+
+/// The Rice coded residuals of a channel, which are read at once,
+/// as the codes of a row do not end at a byte boundary.
+struct Residuals {
+    data: Vec<u8>,
+    /// The position of the residuals relative to the start of the channel.
+    offset: usize,
+    decoder: ResidualDecoder,
+}
+
+impl<R: Read> Input<R> {
+    fn decode_row<P: Pixel>(
+        &mut self,
+        decoded: &mut Decoded<P, RowRing<P>>,
+        end: usize,
+        width: usize,
+        row_data: &mut [u8],
+    ) -> Result<(), DecodeError> {
+        if let Some(residuals) = &mut self.residuals {
+            let mut encoded = Encoded::new(&residuals.data);
+            encoded.channel = self.channel;
+            encoded.offset = residuals.offset;
+
+            decode_residuals(&mut encoded, &mut residuals.decoder, decoded, end)?;
+        }
+
+        // Rows, which end with masked pixels, may be complete without reading further opcodes.
+        decoded.skip_masked(end);
+
+        while decoded.index < end {
+            // DEFAULT opcodes must not be split between two chunks.
+            if self.end - self.start <= P::SIZE && self.remaining > 0 {
+                self.refill()?;
+            }
+
+            let stop = if self.remaining == 0 {
+                self.end
+            } else {
+                self.end - P::SIZE
+            };
+
+            let mut encoded = Encoded::new(&self.chunk[self.start..self.end]);
+            encoded.channel_size = stop - self.start;
+            encoded.channel = self.channel;
+            encoded.offset = self.position();
+
+            if encoded.is_empty() {
+                return Err(encoded.corrupt(0));
+            }
+
+            decode(&mut encoded, decoded, end)?;
+
+            self.start += encoded.index;
+        }
+
+        for (x, bytes) in row_data.chunks_exact_mut(P::SIZE).enumerate() {
+            decoded.target.get(end - width + x).write(bytes);
+        }
+
+        Ok(())
+    }
+
+    fn read_exact(&mut self, data: &mut [u8]) -> Result<(), DecodeError> {
+        if let Err(error) = self.reader.read_exact(data) {
+            return Err(self.read_error(error));
+        }
+
+        if self.checksums.is_some() {
+            self.crc.update(data);
+        }
+
+        self.remaining -= data.len();
+
+        Ok(())
+    }
+
+    /// Reads the remaining encoded data of the current channel, which holds its residuals.
+    ///
+    /// The buffer only grows as data arrives, so a corrupt channel size does not cause a huge allocation.
+    fn read_residuals(&mut self) -> Result<(), DecodeError> {
+        let offset = self.position();
+        let mut data = Vec::new();
+
+        let size = (&mut self.reader)
+            .take(self.remaining as u64)
+            .read_to_end(&mut data)?;
+
+        if size != self.remaining {
+            return Err(DecodeError::InsufficientChannelData {
+                channel: self.channel,
+                offset: offset + size,
+            });
+        }
+
+        if self.checksums.is_some() {
+            self.crc.update(&data);
+        }
+
+        self.remaining = 0;
+
+        self.residuals = Some(Residuals {
+            data,
+            offset,
+            decoder: ResidualDecoder::default(),
+        });
+
+        Ok(())
+    }
+
+    fn refill(&mut self) -> Result<(), DecodeError> {
+        self.chunk.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        let size = self.remaining.min(CHUNK_SIZE - self.end);
+
+        if let Err(error) = self
+            .reader
+            .read_exact(&mut self.chunk[self.end..self.end + size])
+        {
+            return Err(self.read_error(error));
+        }
+
+        if self.checksums.is_some() {
+            self.crc.update(&self.chunk[self.end..self.end + size]);
+        }
+
+        self.end += size;
+        self.remaining -= size;
+
+        Ok(())
+    }
+
+    /// Returns the position of the next encoded byte relative to the start of the channel.
+    fn position(&self) -> usize {
+        self.channel_size - self.remaining - (self.end - self.start)
+    }
+
+    /// Returns the error for a failed read of the encoded data of the current channel.
+    fn read_error(&self, error: io::Error) -> DecodeError {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => DecodeError::InsufficientChannelData {
+                channel: self.channel,
+                offset: self.channel_size - self.remaining,
+            },
+            _ => DecodeError::IoError(error),
+        }
+    }
+
+    /// Ensures that the encoded data of the current channel has been consumed entirely
+    /// and matches its checksum.
+    fn finish_channel(&self) -> Result<(), DecodeError> {
+        if self.start != self.end || self.remaining != 0 {
+            return Err(DecodeError::CorruptData {
+                channel: self.channel,
+                offset: self.position(),
+            });
+        }
+
+        if let Some(residuals) = &self.residuals {
+            let size = residuals.decoder.bytes_read();
+
+            if size != residuals.data.len() {
+                return Err(DecodeError::CorruptData {
+                    channel: self.channel,
+                    offset: residuals.offset + size,
+                });
+            }
+        }
+
+        if let Some(checksums) = self.checksums {
+            if self.crc.finish() != checksums[self.channel] {
+                return Err(DecodeError::ChecksumMismatch {
+                    channel: self.channel,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The buffers for decoding a row of tiles of a tiled image.
+struct TileRow {
+    tiles: Tiles,
+    /// The encoded data of the current tile.
+    encoded: Vec<u8>,
+    /// The decoded pixels of the current tile.
+    tile: Vec<u8>,
+    /// The decoded rows of the current row of tiles.
+    rows: Vec<u8>,
+    /// The coding of the compressed tiles.
+    coding: Coding,
+}
+
+impl TileRow {
+    /// Reads and decodes the tiles of the row of tiles `row` of the current channel.
+    fn decode<R: Read>(
+        &mut self,
+        input: &mut Input<R>,
+        mask: Option<&ChannelMask>,
+        descriptor: &DTM,
+        row: usize,
+    ) -> Result<(), DecodeError> {
+        let tiling = &self.tiles.tiling;
+        let width = descriptor.width as usize;
+        let pixel_size = descriptor.pixel_size as usize;
+
+        for tile in row * tiling.columns..(row + 1) * tiling.columns {
+            let rect = tiling.rect(tile);
+            let range = self.tiles.range(input.channel, tile);
+
+            self.encoded.resize(range.len(), 0);
+            input.read_exact(&mut self.encoded)?;
+
+            let mut encoded = Encoded::new(&self.encoded);
+            encoded.channel = input.channel;
+            encoded.offset = range.start;
+
+            let mask = mask.map(|mask| mask.crop(&rect, width));
+            let tile = &mut self.tile[..rect.pixel_count() * pixel_size];
+
+            with_pixel!(descriptor, P => {
+                decode_tile::<P>(encoded, &rect, mask, self.coding, tile)?
+            });
+
+            for (y, pixels) in tile.chunks_exact(rect.width * pixel_size).enumerate() {
+                let start = (y * width + rect.x) * pixel_size;
+                self.rows[start..start + pixels.len()].copy_from_slice(pixels);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The decoder state for the pixel type of the image.
+///
+/// The reader dispatches once per row, so the pixel loop stays monomorphized.
+trait RowDecoder<R> {
+    fn decode_row(
+        &mut self,
+        input: &mut Input<R>,
+        end: usize,
+        width: usize,
+        row_data: &mut [u8],
+    ) -> Result<(), DecodeError>;
+
+    fn next_channel(&mut self);
+
+    fn set_mask(&mut self, mask: Option<ChannelMask>);
+
+    fn set_predictors(&mut self, predictors: Option<Vec<Predictor>>);
+
+    fn mask(&self) -> Option<&ChannelMask>;
+}
+
+impl<P: Pixel, R: Read> RowDecoder<R> for Decoded<P, RowRing<P>> {
+    #[inline]
+    fn decode_row(
+        &mut self,
+        input: &mut Input<R>,
+        end: usize,
+        width: usize,
+        row_data: &mut [u8],
+    ) -> Result<(), DecodeError> {
+        input.decode_row(self, end, width, row_data)
+    }
+
+    #[inline]
+    fn next_channel(&mut self) {
+        Decoded::next_channel(self);
+    }
+
+    #[inline]
+    fn set_mask(&mut self, mask: Option<ChannelMask>) {
+        self.mask = mask;
+    }
+
+    #[inline]
+    fn set_predictors(&mut self, predictors: Option<Vec<Predictor>>) {
+        self.predictors = predictors;
+    }
+
+    #[inline]
+    fn mask(&self) -> Option<&ChannelMask> {
+        self.mask.as_ref()
+    }
+}
+
+/// A ring buffer holding the last decoded pixels of a channel.
+///
+/// The paeth filter only looks one row back, so the buffer holds a single row
+/// plus the pixels an opcode may decode past the end of the current row.
+pub(crate) struct RowRing<P> {
+    data: Vec<P>,
+}
+
+impl<P: Pixel> RowRing<P> {
+    pub(crate) fn new(width: usize) -> Result<Self, DecodeError> {
+        Ok(RowRing {
+            data: try_vec(P::default(), width + MAX_OVERSHOOT)?,
+        })
+    }
+
+    fn decoded(descriptor: &DTM) -> Result<Decoded<P, Self>, DecodeError> {
+        Ok(Decoded::new(
+            descriptor.width as usize,
+            descriptor.height as usize,
+            RowRing::new(row_width(descriptor))?,
+        ))
+    }
+}
+
+/// Returns the number of pixels of the row buffers, which are empty for images without pixels.
+fn row_width(descriptor: &DTM) -> usize {
+    match descriptor.image_size() {
+        0 => 0,
+        _ => descriptor.width as usize,
+    }
+}
+
+impl<P: Pixel> Target<P> for RowRing<P> {
+    #[inline]
+    fn get(&self, index: usize) -> P {
+        self.data[index % self.data.len()]
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, pixel: P) {
+        let len = self.data.len();
+        self.data[index % len] = pixel;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        testing::{descriptor, terrain},
+        SampleFormat, DTM_HEADER_SIZE,
+    };
+
+    /// A source, which delivers a single byte per read.
+    struct ByteReader<'a>(&'a [u8]);
+
+    impl Read for ByteReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&byte, rest)), Some(first)) => {
+                    *first = byte;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    /// Returns the channel, index and data of every row, in the order the reader returns them.
+    fn rows<R: Read>(mut reader: DtmReader<R>) -> Result<Vec<(u32, u32, Vec<u8>)>, DecodeError> {
+        let mut rows = Vec::new();
+
+        while let Some(row) = reader.next_row()? {
+            rows.push((row.channel, row.y, row.data.to_vec()));
+        }
+
+        Ok(rows)
+    }
+
+    fn encoders(descriptor: DTM) -> [Encoder; 8] {
+        let valid = (0..descriptor.pixel_count()).map(|i| i % 11 != 3).collect();
+
+        [
+            Encoder::new(descriptor),
+            Encoder::new(descriptor).checksums(true),
+            Encoder::new(descriptor).tiles(16, 8),
+            Encoder::new(descriptor).row_groups(5),
+            Encoder::new(descriptor).adaptive_prediction(true),
+            Encoder::new(descriptor).entropy_coding(true),
+            Encoder::new(descriptor).inter_channel_prediction(true),
+            Encoder::new(descriptor).validity_mask(1, valid),
+        ]
+    }
+
+    #[test]
+    fn reads_one_byte_at_a_time() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 37, 29);
+        let decoded = terrain(&descriptor);
+
+        for encoder in encoders(descriptor) {
+            let encoded = encoder.encode_alloc(&decoded).unwrap();
+            let expected = rows(DtmReader::new(encoded.as_slice()).unwrap()).unwrap();
+            assert_eq!(expected.len(), 2 * 29);
+
+            let reader = DtmReader::new(ByteReader(&encoded)).unwrap();
+            assert_eq!(rows(reader).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_channels_ending_mid_row() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 1, 37, 29);
+        let encoded = Encoder::new(descriptor)
+            .encode_alloc(&terrain(&descriptor))
+            .unwrap();
+        let header = DTM::decode_header(&encoded).unwrap();
+        let size = header.channel_sizes[0] / 2;
+
+        // The header declares the shortened channel, so the opcodes run out in the middle of a row.
+        let mut truncated = encoded[..header.header_size + size].to_vec();
+        truncated[20..24].copy_from_slice(&(size as u32).to_be_bytes());
+
+        let mut reader = DtmReader::new(truncated.as_slice()).unwrap();
+        let mut count = 0;
+
+        let error = loop {
+            match reader.next_row() {
+                Ok(Some(_)) => count += 1,
+                Ok(None) => panic!("the image ended after {count} rows"),
+                Err(error) => break error,
+            }
+        };
+
+        assert!(count > 0 && count < 29);
+        assert!(matches!(error, DecodeError::CorruptData { channel: 0, .. }));
+
+        // A stream ending before the declared channel size is reported as missing data.
+        let reader = DtmReader::new(&encoded[..encoded.len() - 1]).unwrap();
+        assert!(matches!(
+            rows(reader),
+            Err(DecodeError::InsufficientChannelData { channel: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn finishes_channels_without_rows() {
+        for descriptor in [
+            descriptor(2, SampleFormat::Unsigned, 3, 37, 0),
+            descriptor(2, SampleFormat::Unsigned, 3, 0, 29),
+        ] {
+            for encoder in [
+                Encoder::new(descriptor),
+                Encoder::new(descriptor).checksums(true),
+                Encoder::new(descriptor).tiles(16, 8),
+            ] {
+                let encoded = encoder.encode_alloc(&[]).unwrap();
+                let mut reader = DtmReader::new(encoded.as_slice()).unwrap();
+
+                assert!(reader.next_row().unwrap().is_none());
+                assert!(reader.next_row().unwrap().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn skips_rows_without_pixels() {
+        // The rows of an image without columns are not iterated, however many there are.
+        let descriptor = descriptor(1, SampleFormat::Unsigned, 4, 0, u32::MAX);
+        let encoded = Encoder::new(descriptor)
+            .checksums(true)
+            .encode_alloc(&[])
+            .unwrap();
+
+        let mut reader = DtmReader::new(encoded.as_slice()).unwrap();
+        assert!(reader.next_row().unwrap().is_none());
+
+        // The checksums of the empty channels are still verified.
+        let mut corrupt = encoded.clone();
+        corrupt[DTM_HEADER_SIZE + 8] ^= 1;
+
+        let mut reader = DtmReader::new(corrupt.as_slice()).unwrap();
+        assert!(matches!(
+            reader.next_row(),
+            Err(DecodeError::ChecksumMismatch { channel: 2 })
+        ));
+    }
+}
@@ -0,0 +1,425 @@
+//! The entropy coder of the high compression mode, which replaces the opcodes.
+//!
+//! The residuals of the valid pixels are mapped to unsigned values and stored as Golomb-Rice codes,
+//! whose parameter adapts to the mean magnitude of the preceding residuals as in LOCO-I.
+//! While the parameter is zero, runs of zero residuals are stored as their Elias gamma coded length,
+//! followed by the nonzero residual ending the run.
+//! Runs of the maximum length are not followed by a residual, as the next run continues them.
+//! The bits are stored most significant bit first and the last byte is padded with zeros.
+
+/// The number of residuals, after which the statistics are halved to follow local changes.
+const RESET: u64 = 64;
+
+/// The longest unary prefix of a Rice code, which is followed by the value as a u32 instead.
+const LIMIT: u32 = 32;
+
+/// The longest run of zero residuals stored by a single Elias gamma code.
+///
+/// Its code is 25 bits long, which limits the number of pixels a byte of the stream can encode.
+const MAX_RUN: u64 = 4095;
+
+/// The largest number of pixels, which a byte of the stream encodes.
+pub(crate) const MAX_PIXELS_PER_BYTE: usize = (8 * MAX_RUN as usize).div_ceil(25);
+
+/// The longest unary prefix of an Elias gamma code, i.e. the prefix of the code of `MAX_RUN + 1`.
+const MAX_GAMMA_PREFIX: u32 = 12;
+
+/// The statistics of the preceding residuals, which select the Rice parameter.
+#[derive(Clone, Copy, Debug)]
+struct Statistics {
+    magnitude: u64,
+    count: u64,
+}
+
+impl Default for Statistics {
+    fn default() -> Self {
+        Self {
+            magnitude: 4,
+            count: 1,
+        }
+    }
+}
+
+impl Statistics {
+    /// Returns the smallest parameter, for which the code of the mean magnitude has no unary part.
+    #[inline]
+    fn parameter(&self) -> u32 {
+        let mut parameter = 0;
+
+        while parameter < 31 && self.count << parameter < self.magnitude {
+            parameter += 1;
+        }
+
+        parameter
+    }
+
+    #[inline]
+    fn update(&mut self, residual: i32) {
+        self.magnitude += residual.unsigned_abs() as u64;
+        self.count += 1;
+
+        if self.count == RESET {
+            self.magnitude >>= 1;
+            self.count >>= 1;
+        }
+    }
+}
+
+/// Maps residuals around zero to small unsigned values.
+#[inline]
+fn zigzag(residual: i32) -> u32 {
+    ((residual << 1) ^ (residual >> 31)) as u32
+}
+
+#[inline]
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+/// Encodes the residuals of a channel or tile into a bit stream.
+#[derive(Debug, Default)]
+pub(crate) struct ResidualEncoder {
+    data: Vec<u8>,
+    buffer: u64,
+    bits: u32,
+    statistics: Statistics,
+    /// The length of the current run of zero residuals, if a run has been started.
+    run: Option<u64>,
+}
+
+impl ResidualEncoder {
+    /// Appends the residual of the next valid pixel.
+    pub(crate) fn push(&mut self, residual: i32) {
+        match self.run {
+            Some(run) if residual == 0 => {
+                self.run = match run + 1 {
+                    MAX_RUN => {
+                        self.write_gamma(MAX_RUN);
+                        None
+                    }
+                    run => Some(run),
+                }
+            }
+            Some(run) => {
+                self.write_gamma(run);
+                self.write_rice(zigzag(residual) - 1);
+                self.run = None;
+            }
+            None if self.statistics.parameter() == 0 => {
+                if residual == 0 {
+                    self.run = Some(1);
+                } else {
+                    self.write_gamma(0);
+                    self.write_rice(zigzag(residual) - 1);
+                }
+            }
+            None => self.write_rice(zigzag(residual)),
+        }
+
+        self.statistics.update(residual);
+    }
+
+    /// Ends a pending run and returns the bit stream.
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        if let Some(run) = self.run.take() {
+            self.write_gamma(run);
+        }
+
+        if self.bits > 0 {
+            self.data.push((self.buffer << (8 - self.bits)) as u8);
+        }
+
+        self.data
+    }
+
+    fn write_rice(&mut self, value: u32) {
+        let parameter = self.statistics.parameter();
+        let quotient = value >> parameter;
+
+        if quotient < LIMIT {
+            self.write(((1 << quotient) - 1) << 1, quotient + 1);
+            self.write(value as u64, parameter);
+        } else {
+            self.write((1 << LIMIT) - 1, LIMIT);
+            self.write(value as u64, 32);
+        }
+    }
+
+    /// Writes `run + 1` as an Elias gamma code.
+    fn write_gamma(&mut self, run: u64) {
+        let value = run + 1;
+        let length = u64::BITS - value.leading_zeros();
+
+        self.write(0, length - 1);
+        self.write(value, length);
+    }
+
+    /// Writes the lowest `count` bits of `value`, where `count` must not exceed 32.
+    #[inline]
+    fn write(&mut self, value: u64, count: u32) {
+        self.buffer = (self.buffer << count) | (value & ((1 << count) - 1));
+        self.bits += count;
+
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.data.push((self.buffer >> self.bits) as u8);
+        }
+    }
+}
+
+/// Decodes the residuals of a channel or tile from a bit stream.
+///
+/// The decoder only holds the position inside the stream, which is passed to every call,
+/// so that it can be kept across multiple calls.
+#[derive(Debug, Default)]
+pub(crate) struct ResidualDecoder {
+    position: usize,
+    statistics: Statistics,
+    /// The number of zero residuals left in the current run.
+    run: u64,
+    /// Whether the next residual ends a run and is thus nonzero.
+    ends_run: bool,
+}
+
+impl ResidualDecoder {
+    /// Creates a decoder for the bit stream starting at the byte `start`.
+    pub(crate) fn new(start: usize) -> Self {
+        Self {
+            position: start * 8,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of bytes read so far, including the partially read byte.
+    #[inline]
+    pub(crate) fn bytes_read(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+
+    /// Returns whether the last run has been completed.
+    #[inline]
+    pub(crate) fn is_finished(&self) -> bool {
+        self.run == 0
+    }
+
+    /// Reads the residual of the next valid pixel or returns `None` if the stream is malformed.
+    #[inline]
+    pub(crate) fn next(&mut self, data: &[u8]) -> Option<i32> {
+        let residual = if self.run > 0 {
+            self.run -= 1;
+            0
+        } else if self.ends_run {
+            self.ends_run = false;
+            unzigzag(self.read_rice(data)?.checked_add(1)?)
+        } else if self.statistics.parameter() == 0 {
+            match self.read_gamma(data)? {
+                0 => unzigzag(self.read_rice(data)?.checked_add(1)?),
+                run if run > MAX_RUN => return None,
+                run => {
+                    self.run = run - 1;
+                    self.ends_run = run < MAX_RUN;
+                    0
+                }
+            }
+        } else {
+            unzigzag(self.read_rice(data)?)
+        };
+
+        self.statistics.update(residual);
+
+        Some(residual)
+    }
+
+    fn read_rice(&mut self, data: &[u8]) -> Option<u32> {
+        let parameter = self.statistics.parameter();
+        let quotient = self.peek(data).leading_ones().min(LIMIT);
+
+        if quotient == LIMIT {
+            self.skip(data, LIMIT)?;
+            return self.read(data, 32);
+        }
+
+        // The unary prefix is terminated by a zero bit.
+        self.skip(data, quotient + 1)?;
+        let remainder = self.read(data, parameter)?;
+
+        quotient
+            .checked_shl(parameter)
+            .filter(|value| value >> parameter == quotient)
+            .map(|value| value | remainder)
+    }
+
+    /// Reads an Elias gamma code and returns the run length it stores.
+    fn read_gamma(&mut self, data: &[u8]) -> Option<u64> {
+        let zeros = self.peek(data).leading_zeros();
+
+        if zeros > MAX_GAMMA_PREFIX {
+            return None;
+        }
+
+        self.skip(data, zeros)?;
+        let value = self.read(data, zeros + 1)?;
+
+        Some(value as u64 - 1)
+    }
+
+    /// Returns the next 64 bits of the stream, which are padded with zeros past its end.
+    #[inline]
+    fn peek(&self, data: &[u8]) -> u64 {
+        let start = (self.position / 8).min(data.len());
+        let end = (start + 8).min(data.len());
+
+        let mut bytes = [0; 8];
+        bytes[..end - start].copy_from_slice(&data[start..end]);
+
+        u64::from_be_bytes(bytes) << (self.position % 8)
+    }
+
+    #[inline]
+    fn skip(&mut self, data: &[u8], count: u32) -> Option<()> {
+        if self.position + count as usize > data.len() * 8 {
+            return None;
+        }
+
+        self.position += count as usize;
+        Some(())
+    }
+
+    /// Reads `count` bits, where `count` must not exceed 32.
+    #[inline]
+    fn read(&mut self, data: &[u8], count: u32) -> Option<u32> {
+        if count == 0 {
+            return Some(0);
+        }
+
+        let value = (self.peek(data) >> (64 - count)) as u32;
+        self.skip(data, count)?;
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::Encoder,
+        testing::{descriptor, features, roundtrip, terrain},
+        SampleFormat, FEATURE_ENTROPY,
+    };
+
+    fn assert_roundtrips(residuals: &[i32]) {
+        let mut encoder = ResidualEncoder::default();
+        residuals
+            .iter()
+            .for_each(|&residual| encoder.push(residual));
+        let data = encoder.finish();
+
+        let mut decoder = ResidualDecoder::new(0);
+
+        for &residual in residuals {
+            assert_eq!(decoder.next(&data), Some(residual));
+        }
+
+        assert!(decoder.is_finished());
+        assert_eq!(decoder.bytes_read(), data.len());
+    }
+
+    #[test]
+    fn maps_residuals_to_unsigned_values() {
+        for residual in [0, 1, -1, 2, -2, 1000, -1000, i32::MAX, i32::MIN] {
+            assert_eq!(unzigzag(zigzag(residual)), residual);
+        }
+
+        assert_eq!([0, -1, 1, -2, 2].map(zigzag), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn roundtrips_residuals() {
+        let mut state = 0x9e37_79b9_u32;
+        let mut noise = |bits: u32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> (32 - bits)) as i32 - (1 << (bits - 1))
+        };
+
+        let small: Vec<i32> = (0..5000).map(|_| noise(3)).collect();
+        let large: Vec<i32> = (0..5000).map(|_| noise(20)).collect();
+        let sparse: Vec<i32> = (0..5000)
+            .map(|index| if index % 37 == 0 { noise(8) } else { 0 })
+            .collect();
+
+        assert_roundtrips(&small);
+        assert_roundtrips(&large);
+        assert_roundtrips(&sparse);
+        assert_roundtrips(&[i32::MAX, i32::MIN, 0, i32::MIN, 1, -1, i32::MAX]);
+        assert_roundtrips(&[]);
+    }
+
+    #[test]
+    fn roundtrips_long_runs() {
+        for length in [1, MAX_RUN - 1, MAX_RUN, MAX_RUN + 1, 3 * MAX_RUN, 10_000] {
+            let mut residuals = vec![0; length as usize];
+            assert_roundtrips(&residuals);
+
+            residuals.push(5);
+            residuals.push(0);
+            assert_roundtrips(&residuals);
+        }
+    }
+
+    #[test]
+    fn bounds_pixels_per_byte() {
+        let mut encoder = ResidualEncoder::default();
+        (0..100_000).for_each(|_| encoder.push(0));
+
+        assert!(encoder.finish().len() * MAX_PIXELS_PER_BYTE >= 100_000);
+    }
+
+    #[test]
+    fn roundtrips_entropy_coded_images() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (4, SampleFormat::Unsigned),
+            (2, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in [1, 3] {
+                let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                let decoded = terrain(&descriptor);
+
+                let encoder = Encoder::new(descriptor).entropy_coding(true);
+                let encoded = roundtrip(&encoder, &decoded);
+                assert_eq!(features(&encoded), FEATURE_ENTROPY);
+
+                roundtrip(&encoder.clone().tiles(16, 8), &decoded);
+                roundtrip(&encoder.clone().adaptive_prediction(true), &decoded);
+                roundtrip(&encoder.inter_channel_prediction(true), &decoded);
+            }
+        }
+    }
+
+    #[test]
+    fn roundtrips_flat_images() {
+        // The runs of zero residuals are longer than a single Elias gamma code covers.
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 300, 100);
+        let decoded: Vec<u8> = (0..descriptor.image_size() / 2)
+            .flat_map(|index| match index % 2 {
+                0 => 1234u16.to_le_bytes(),
+                _ => [0, 0],
+            })
+            .collect();
+
+        roundtrip(&Encoder::new(descriptor).entropy_coding(true), &decoded);
+        roundtrip(
+            &Encoder::new(descriptor)
+                .entropy_coding(true)
+                .validity_mask(1, (0..30_000).map(|index| index % 4001 != 0).collect()),
+            &decoded,
+        );
+    }
+}
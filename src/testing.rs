@@ -0,0 +1,197 @@
+//! Synthetic images and round trip checks shared by the tests of the format features.
+
+use crate::{
+    encode::Encoder, reader::DtmReader, SampleFormat, DTM, DTM_LEGACY_HEADER_SIZE,
+    FEATURE_CHECKSUMS, FEATURE_CHUNKS, FEATURE_ENTROPY, FEATURE_INTER_CHANNEL, FEATURE_PREDICTORS,
+    FEATURE_TILES,
+};
+
+/// An image in `tests/data`, which was written by an earlier version of the encoder.
+pub(crate) struct RegressionFile {
+    pub(crate) name: &'static str,
+    pub(crate) encoded: &'static [u8],
+    /// The feature flags of the header, or `None` for the legacy header, which has none.
+    pub(crate) features: Option<u32>,
+    /// The descriptor of the [`terrain`], which the file holds.
+    pub(crate) descriptor: DTM,
+}
+
+macro_rules! regression_file {
+    ($name:literal, $features:expr, $descriptor:expr) => {
+        RegressionFile {
+            name: $name,
+            encoded: include_bytes!(concat!("../tests/data/", $name)),
+            features: $features,
+            descriptor: $descriptor,
+        }
+    };
+}
+
+/// The regression files, which every version of the decoder has to read.
+pub(crate) const REGRESSION_FILES: [RegressionFile; 8] = [
+    regression_file!(
+        "legacy.dtm",
+        None,
+        descriptor(2, SampleFormat::Unsigned, 2, 37, 29)
+    ),
+    regression_file!(
+        "plain.dtm",
+        Some(0),
+        descriptor(2, SampleFormat::Unsigned, 2, 37, 29)
+    ),
+    regression_file!(
+        "checksums.dtm",
+        Some(FEATURE_CHECKSUMS),
+        descriptor(1, SampleFormat::Unsigned, 3, 37, 29)
+    ),
+    regression_file!(
+        "chunks.dtm",
+        Some(FEATURE_CHUNKS),
+        descriptor(2, SampleFormat::Signed, 1, 37, 29)
+    ),
+    regression_file!(
+        "tiles.dtm",
+        Some(FEATURE_TILES),
+        descriptor(4, SampleFormat::Unsigned, 2, 37, 29)
+    ),
+    regression_file!(
+        "predictors.dtm",
+        Some(FEATURE_PREDICTORS),
+        descriptor(2, SampleFormat::Unsigned, 1, 37, 29)
+    ),
+    regression_file!(
+        "inter_channel.dtm",
+        Some(FEATURE_INTER_CHANNEL),
+        descriptor(2, SampleFormat::Unsigned, 3, 37, 29)
+    ),
+    regression_file!(
+        "entropy.dtm",
+        Some(FEATURE_ENTROPY),
+        descriptor(4, SampleFormat::Float, 1, 37, 29)
+    ),
+];
+
+/// Returns the descriptor of an image with the given pixel type and size.
+pub(crate) const fn descriptor(
+    pixel_size: u32,
+    sample_format: SampleFormat,
+    channel_count: u32,
+    width: u32,
+    height: u32,
+) -> DTM {
+    DTM {
+        pixel_size,
+        sample_format,
+        channel_count,
+        width,
+        height,
+    }
+}
+
+/// Returns the pixels of a synthetic terrain, which contains flat areas, gentle slopes and spikes,
+/// so that every opcode is used.
+///
+/// The pixels only depend on the descriptor, so the regression files can be decoded and compared.
+pub(crate) fn terrain(descriptor: &DTM) -> Vec<u8> {
+    let pixel_size = descriptor.pixel_size as usize;
+    let mut state = 0x2545_f491_u32;
+    let mut decoded = Vec::with_capacity(descriptor.image_size());
+
+    for y in 0..descriptor.height {
+        for x in 0..descriptor.width {
+            for channel in 0..descriptor.channel_count {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+
+                let slope = 500 + x * 3 + y * 5 + (x * y) % 7 + channel * 40;
+                let value = if x < descriptor.width / 4 {
+                    500 + channel
+                } else {
+                    match state % 16 {
+                        0 => state >> 8,
+                        1..=4 => slope + (state >> 28),
+                        _ => slope,
+                    }
+                };
+
+                match descriptor.sample_format {
+                    SampleFormat::Float => {
+                        decoded.extend_from_slice(&(value as f32 * 0.25).to_le_bytes())
+                    }
+                    _ => decoded.extend_from_slice(&value.to_le_bytes()[..pixel_size]),
+                }
+            }
+        }
+    }
+
+    decoded
+}
+
+/// Encodes the pixels and checks that they round trip through every decoding entry point.
+pub(crate) fn roundtrip(encoder: &Encoder, decoded: &[u8]) -> Vec<u8> {
+    let encoded = encoder.encode_alloc(decoded).unwrap();
+    assert_decodes(&encoded, decoded);
+
+    encoded
+}
+
+/// Checks that the encoded image decodes to `expected` as a whole, row by row and by region.
+pub(crate) fn assert_decodes(encoded: &[u8], expected: &[u8]) {
+    let (descriptor, decoded) = DTM::decode_alloc(encoded).unwrap();
+    assert_eq!(decoded, expected);
+    assert_eq!(DTM::verify(encoded).unwrap(), descriptor);
+
+    let pixel_size = descriptor.pixel_size as usize;
+    let channel_count = descriptor.channel_count as usize;
+    let width = descriptor.width as usize;
+
+    let mut rows = vec![0; descriptor.image_size()];
+    let mut reader = DtmReader::new(encoded).unwrap();
+
+    reader
+        .read_rows(|row| {
+            let start = row.y as usize * width;
+
+            for (x, pixel) in row.data.chunks_exact(pixel_size).enumerate() {
+                let index = ((start + x) * channel_count + row.channel as usize) * pixel_size;
+                rows[index..index + pixel_size].copy_from_slice(pixel);
+            }
+        })
+        .unwrap();
+
+    assert_eq!(rows, expected);
+
+    let (x, y) = (descriptor.width / 3, descriptor.height / 4);
+    let (region_width, region_height) = (descriptor.width / 2, descriptor.height / 2);
+    let mut region = vec![0; (region_width * region_height) as usize * pixel_size * channel_count];
+    DTM::decode_region(encoded, x, y, region_width, region_height, &mut region).unwrap();
+
+    let row_size = region_width as usize * pixel_size * channel_count;
+
+    for (row, decoded) in region.chunks_exact(row_size).enumerate() {
+        let start =
+            (y as usize + row) * descriptor.row_size() + x as usize * pixel_size * channel_count;
+        assert_eq!(decoded, &expected[start..start + row_size]);
+    }
+}
+
+/// Returns the feature flags stored in the header of an encoded image.
+pub(crate) fn features(encoded: &[u8]) -> u32 {
+    u32::from_be_bytes(encoded[8..12].try_into().unwrap())
+}
+
+#[test]
+fn decodes_regression_files() {
+    for file in &REGRESSION_FILES {
+        let header = DTM::decode_header(file.encoded).unwrap();
+        assert_eq!(header.descriptor, file.descriptor, "{}", file.name);
+
+        match file.features {
+            Some(flags) => assert_eq!(features(file.encoded), flags, "{}", file.name),
+            None => assert_eq!(header.header_size, DTM_LEGACY_HEADER_SIZE, "{}", file.name),
+        }
+
+        assert_decodes(file.encoded, &terrain(&file.descriptor));
+    }
+}
@@ -0,0 +1,328 @@
+//! The tiled layout, in which every channel is split into tiles, that are encoded independently.
+//!
+//! Each tile starts with an empty cache and is predicted as if it was an image of its own,
+//! so a region of the image can be decoded from the tiles it intersects.
+//! The tiles of a channel are stored row by row and their offsets are stored in the header.
+
+use crate::{decode::DecodeError, DTM};
+use std::ops::Range;
+
+/// A rectangle of pixels inside an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Rect {
+    pub(crate) x: usize,
+    pub(crate) y: usize,
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+}
+
+impl Rect {
+    #[inline]
+    pub(crate) fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Returns the index inside an image of the given width of the pixel
+    /// at `index` inside the rectangle.
+    #[inline]
+    pub(crate) fn image_index(&self, index: usize, image_width: usize) -> usize {
+        (self.y + index / self.width) * image_width + self.x + index % self.width
+    }
+}
+
+/// The division of the channels of an image into tiles.
+///
+/// The tiles in the last column and row are cut off at the border of the image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tiling {
+    pub(crate) tile_width: usize,
+    pub(crate) tile_height: usize,
+    pub(crate) columns: usize,
+    pub(crate) rows: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Tiling {
+    /// Divides the image into tiles of the given size, which must not be zero.
+    pub(crate) fn new(descriptor: &DTM, tile_width: u32, tile_height: u32) -> Self {
+        let (width, height) = (descriptor.width as usize, descriptor.height as usize);
+        let (tile_width, tile_height) = (tile_width as usize, tile_height as usize);
+
+        Self {
+            tile_width,
+            tile_height,
+            columns: width.div_ceil(tile_width),
+            rows: height.div_ceil(tile_height),
+            width,
+            height,
+        }
+    }
+
+    /// Returns the number of tiles of a channel.
+    #[inline]
+    pub(crate) fn tile_count(&self) -> usize {
+        self.columns * self.rows
+    }
+
+    /// Returns the size of the largest tile, which is not cut off unless the image is smaller.
+    pub(crate) fn largest_tile(&self) -> Rect {
+        Rect {
+            x: 0,
+            y: 0,
+            width: self.tile_width.min(self.width),
+            height: self.tile_height.min(self.height),
+        }
+    }
+
+    /// Returns the pixels covered by the tile.
+    pub(crate) fn rect(&self, tile: usize) -> Rect {
+        let x = tile % self.columns * self.tile_width;
+        let y = tile / self.columns * self.tile_height;
+
+        Rect {
+            x,
+            y,
+            width: self.tile_width.min(self.width - x),
+            height: self.tile_height.min(self.height - y),
+        }
+    }
+
+    /// Returns the tiles intersecting the region in the order they are stored.
+    pub(crate) fn tiles(&self, region: Rect) -> impl Iterator<Item = usize> + '_ {
+        let columns =
+            region.x / self.tile_width..(region.x + region.width).div_ceil(self.tile_width);
+        let rows = if region.pixel_count() == 0 {
+            0..0
+        } else {
+            region.y / self.tile_height..(region.y + region.height).div_ceil(self.tile_height)
+        };
+
+        rows.flat_map(move |row| {
+            columns
+                .clone()
+                .map(move |column| row * self.columns + column)
+        })
+    }
+}
+
+/// The tiling of an encoded image and the offsets of its encoded tiles.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Tiles {
+    pub(crate) tiling: Tiling,
+    /// The offsets of the tiles relative to the start of their channel, channel by channel.
+    offsets: Vec<usize>,
+    channel_sizes: [usize; 4],
+}
+
+impl Tiles {
+    /// Parses the offset table of the tiles.
+    ///
+    /// The tiles of a channel have to be stored back to back, starting at the start of the channel.
+    pub(crate) fn decode(
+        table: &[u8],
+        tiling: Tiling,
+        channel_sizes: [usize; 4],
+    ) -> Result<Self, DecodeError> {
+        let offsets: Vec<usize> = table
+            .chunks_exact(4)
+            .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()) as usize)
+            .collect();
+
+        // Empty images have no tiles.
+        for (channel, offsets) in offsets.chunks(tiling.tile_count().max(1)).enumerate() {
+            let valid = offsets[0] == 0
+                && offsets.windows(2).all(|offsets| offsets[0] <= offsets[1])
+                && offsets[offsets.len() - 1] <= channel_sizes[channel];
+
+            if !valid {
+                return Err(DecodeError::InvalidTiling);
+            }
+        }
+
+        Ok(Self {
+            tiling,
+            offsets,
+            channel_sizes,
+        })
+    }
+
+    /// Returns the range of the encoded data of the tile relative to the start of its channel.
+    pub(crate) fn range(&self, channel: usize, tile: usize) -> Range<usize> {
+        let offsets = &self.offsets[channel * self.tiling.tile_count()..];
+        let end = match tile + 1 < self.tiling.tile_count() {
+            true => offsets[tile + 1],
+            false => self.channel_sizes[channel],
+        };
+
+        offsets[tile]..end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encode::{EncodeError, Encoder},
+        testing::{descriptor, features, roundtrip, terrain},
+        SampleFormat, FEATURE_TILES,
+    };
+
+    #[test]
+    fn covers_every_pixel_once() {
+        let tiling = Tiling::new(&descriptor(2, SampleFormat::Unsigned, 1, 37, 29), 16, 8);
+        let mut covered = vec![0; 37 * 29];
+
+        assert_eq!((tiling.columns, tiling.rows), (3, 4));
+
+        for tile in 0..tiling.tile_count() {
+            let rect = tiling.rect(tile);
+
+            for index in 0..rect.pixel_count() {
+                covered[rect.image_index(index, 37)] += 1;
+            }
+        }
+
+        assert!(covered.iter().all(|&count| count == 1));
+        assert_eq!(
+            tiling.rect(11),
+            Rect {
+                x: 32,
+                y: 24,
+                width: 5,
+                height: 5
+            }
+        );
+    }
+
+    #[test]
+    fn selects_intersecting_tiles() {
+        let tiling = Tiling::new(&descriptor(2, SampleFormat::Unsigned, 1, 37, 29), 16, 8);
+        let region = |x, y, width, height| Rect {
+            x,
+            y,
+            width,
+            height,
+        };
+
+        let tiles: Vec<_> = tiling.tiles(region(15, 7, 2, 2)).collect();
+        assert_eq!(tiles, [0, 1, 3, 4]);
+
+        let tiles: Vec<_> = tiling.tiles(region(36, 28, 1, 1)).collect();
+        assert_eq!(tiles, [11]);
+
+        assert_eq!(tiling.tiles(region(5, 5, 0, 3)).count(), 0);
+    }
+
+    #[test]
+    fn rejects_invalid_offset_tables() {
+        let tiling = Tiling::new(&descriptor(2, SampleFormat::Unsigned, 1, 37, 29), 16, 16);
+        let table = |offsets: [u32; 6]| -> Vec<u8> {
+            offsets
+                .iter()
+                .flat_map(|offset| offset.to_be_bytes())
+                .collect()
+        };
+        let channel_sizes = [100, 0, 0, 0];
+
+        let tiles = Tiles::decode(
+            &table([0, 10, 20, 20, 50, 60]),
+            tiling.clone(),
+            channel_sizes,
+        );
+        assert_eq!(tiles.unwrap().range(0, 3), 20..50);
+
+        for offsets in [
+            [1, 10, 20, 30, 40, 50],
+            [0, 20, 10, 30, 40, 50],
+            [0, 10, 20, 30, 40, 101],
+        ] {
+            assert!(matches!(
+                Tiles::decode(&table(offsets), tiling.clone(), channel_sizes),
+                Err(DecodeError::InvalidTiling)
+            ));
+        }
+    }
+
+    #[test]
+    fn roundtrips_tiled_images() {
+        let formats = [
+            (1, SampleFormat::Unsigned),
+            (2, SampleFormat::Unsigned),
+            (4, SampleFormat::Signed),
+            (4, SampleFormat::Float),
+        ];
+
+        for (pixel_size, sample_format) in formats {
+            for channel_count in [1, 3] {
+                for (tile_width, tile_height) in [(1, 1), (5, 3), (16, 8), (37, 29), (64, 64)] {
+                    let descriptor = descriptor(pixel_size, sample_format, channel_count, 37, 29);
+                    let encoder = Encoder::new(descriptor).tiles(tile_width, tile_height);
+                    let encoded = roundtrip(&encoder, &terrain(&descriptor));
+
+                    assert_eq!(features(&encoded), FEATURE_TILES);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_every_region() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 37, 29);
+        let decoded = terrain(&descriptor);
+        let encoded = Encoder::new(descriptor)
+            .tiles(16, 8)
+            .encode_alloc(&decoded)
+            .unwrap();
+
+        for (x, y, width, height) in [
+            (0, 0, 37, 29),
+            (15, 7, 2, 2),
+            (36, 28, 1, 1),
+            (3, 9, 30, 17),
+        ] {
+            let row_size = width as usize * 4;
+            let mut region = vec![0; row_size * height as usize];
+            DTM::decode_region(&encoded, x, y, width, height, &mut region).unwrap();
+
+            for (row, region) in region.chunks_exact(row_size).enumerate() {
+                let start = (y as usize + row) * descriptor.row_size() + x as usize * 4;
+                assert_eq!(region, &decoded[start..start + row_size]);
+            }
+        }
+
+        let mut region = vec![0; 8];
+        assert!(matches!(
+            DTM::decode_region(&encoded, 36, 0, 2, 1, &mut region),
+            Err(DecodeError::InvalidRegion)
+        ));
+    }
+
+    #[test]
+    fn decodes_row_groups() {
+        let descriptor = descriptor(2, SampleFormat::Unsigned, 2, 37, 29);
+        let decoded = terrain(&descriptor);
+        let encoder = Encoder::new(descriptor).row_groups(4);
+        let encoded = roundtrip(&encoder, &decoded);
+
+        assert_eq!(features(&encoded), FEATURE_TILES);
+
+        for rows in [0..29, 3..5, 28..29, 8..8] {
+            let mut decoded_rows = vec![0; rows.len() * descriptor.row_size()];
+            DTM::decode_rows(&encoded, rows.clone(), &mut decoded_rows).unwrap();
+
+            let start = rows.start as usize * descriptor.row_size();
+            assert_eq!(decoded_rows, &decoded[start..start + decoded_rows.len()]);
+        }
+
+        for encoder in [
+            encoder.clone().tiles(16, 8),
+            Encoder::new(descriptor).tiles(16, 8).row_groups(4),
+        ] {
+            assert!(matches!(
+                encoder.encode_alloc(&decoded),
+                Err(EncodeError::ConflictingTiling)
+            ));
+        }
+    }
+}